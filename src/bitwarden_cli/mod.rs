@@ -1,10 +1,17 @@
-use crate::bitwarden_cli::BitwardenError::MissingEnvVariable;
+use crate::bitwarden_cli::BitwardenError::MissingSetting;
+use crate::config::Config;
+use base64::Engine;
 use chrono::{DateTime, Utc};
+use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
-use std::env;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Stdio;
 use std::sync::Arc;
 
 use thiserror::Error;
+use tokio::io::AsyncWriteExt;
 use tokio::sync::RwLock;
 use tonic::async_trait;
 use tracing::{error, info};
@@ -24,14 +31,20 @@ pub struct BitwardenCliClient {
     client_id: String,
     client_secret: String,
     client_password: String,
+    /// data directory of the CLI, its default one when `None`
+    appdata_dir: Option<PathBuf>,
+    /// items written in memory instead of the vault, they are read before the vault
+    local_items: Option<Arc<RwLock<HashMap<String, BitwardenItem>>>>,
+    /// the vault is never reached, only the local items are read
+    offline: bool,
 
     storage: Arc<RwLock<BitwardenCliWrapperStorage>>,
 }
 
 #[derive(Error, Debug)]
 pub enum BitwardenError {
-    #[error("missing env variable {0}")]
-    MissingEnvVariable(String),
+    #[error("missing setting {0}")]
+    MissingSetting(String),
     #[error("bw login failed")]
     LoginFailed(String),
     #[error("`bw sync` failed")]
@@ -44,24 +57,94 @@ pub enum BitwardenError {
     ItemNotFound(String),
     #[error("bw get item failed: {0}, error: {1}")]
     GetItemGenericFail(String, String),
+    #[error("bw create item failed: {0}")]
+    CreateItemFailed(String),
+    #[error("bw edit item failed: {0}, error: {1}")]
+    EditItemFailed(String, String),
+    #[error("bw list items failed: {0}, error: {1}")]
+    ListItemsFailed(String, String),
+    #[error("bw generate failed: {0}")]
+    GenerateFailed(String),
+    #[error("`bw lock` failed: {0}")]
+    LockFailed(String),
+    #[error("`bw logout` failed: {0}")]
+    LogoutFailed(String),
     #[error("bitwarden command: {0} failed")]
     IoError(#[from] std::io::Error),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Bitwarden item, the properties which aren't used by the operator are kept in `other` so the
+/// item can be written back by `bw edit item` without losing them
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct BitwardenItem {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub item_type: Option<u8>,
     #[serde(rename = "notes")]
     pub note: Option<String>,
     pub fields: Option<Vec<BitwardenItemField>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub login: Option<BitwardenItemLogin>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revision_date: Option<DateTime<Utc>>,
+    /// Previous login passwords and hidden field values, most recent first
+    pub password_history: Option<Vec<BitwardenPasswordHistory>>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Entry of the item `passwordHistory`, hidden fields are recorded as `<name>: <value>`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitwardenPasswordHistory {
+    pub last_used_date: Option<DateTime<Utc>>,
+    pub password: String,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+impl BitwardenItem {
+    /// Empty secure note, its `id` is assigned by Bitwarden when it is created
+    pub fn secure_note(name: String) -> Self {
+        let mut item = BitwardenItem {
+            name: Some(name),
+            item_type: Some(BITWARDEN_ITEM_TYPE_SECURE_NOTE),
+            fields: Some(vec![]),
+            ..Default::default()
+        };
+        item.other
+            .insert("secureNote".to_string(), serde_json::json!({ "type": 0 }));
+        item
+    }
+}
+
+pub const BITWARDEN_ITEM_TYPE_LOGIN: u8 = 1;
+pub const BITWARDEN_ITEM_TYPE_SECURE_NOTE: u8 = 2;
+pub const BITWARDEN_FIELD_TYPE_HIDDEN: u8 = 1;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct BitwardenItemField {
     pub name: String,
     pub value: String,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub field_type: Option<u8>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitwardenItemLogin {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub totp: Option<String>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -71,9 +154,119 @@ pub struct BitwardenGetItemResponse {
     pub success: bool,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitwardenListItemsResponse {
+    pub data: Option<BitwardenList>,
+    pub success: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitwardenList {
+    pub data: Vec<BitwardenItem>,
+}
+
+/// Options of `bw generate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BitwardenGenerator {
+    Password {
+        length: u8,
+        uppercase: bool,
+        lowercase: bool,
+        numbers: bool,
+        special: bool,
+    },
+    Passphrase {
+        words: u8,
+        separator: String,
+    },
+}
+
+impl BitwardenGenerator {
+    /// Arguments of `bw generate`
+    pub fn args(&self) -> Vec<String> {
+        match self {
+            BitwardenGenerator::Password {
+                length,
+                uppercase,
+                lowercase,
+                numbers,
+                special,
+            } => {
+                let mut args = vec!["--length".to_string(), length.to_string()];
+                for (enabled, arg) in [
+                    (uppercase, "--uppercase"),
+                    (lowercase, "--lowercase"),
+                    (numbers, "--number"),
+                    (special, "--special"),
+                ] {
+                    if *enabled {
+                        args.push(arg.to_string());
+                    }
+                }
+                args
+            }
+            BitwardenGenerator::Passphrase { words, separator } => vec![
+                "--passphrase".to_string(),
+                "--words".to_string(),
+                words.to_string(),
+                "--separator".to_string(),
+                separator.clone(),
+            ],
+        }
+    }
+
+    /// Generates a password the way `bw generate` does, with at least one character of every
+    /// enabled set and without ambiguous characters, for the clients which never reach the vault
+    fn generate_locally(&self) -> Result<String, BitwardenError> {
+        let BitwardenGenerator::Password {
+            length,
+            uppercase,
+            lowercase,
+            numbers,
+            special,
+        } = self
+        else {
+            return Err(BitwardenError::GenerateFailed(
+                "passphrases can't be generated without the vault".to_string(),
+            ));
+        };
+        let charsets = [
+            (uppercase, "ABCDEFGHJKLMNPQRSTUVWXYZ"),
+            (lowercase, "abcdefghijkmnopqrstuvwxyz"),
+            (numbers, "23456789"),
+            (special, "!@#$%^&*"),
+        ]
+        .into_iter()
+        .filter(|(enabled, _)| **enabled)
+        .map(|(_, charset)| charset.as_bytes())
+        .collect::<Vec<_>>();
+        if charsets.is_empty() || charsets.len() > *length as usize {
+            return Err(BitwardenError::GenerateFailed(
+                "the length can't hold a character of every charset".to_string(),
+            ));
+        }
+
+        let mut rng = rand::thread_rng();
+        let all = charsets.concat();
+        let mut password = charsets
+            .iter()
+            .filter_map(|x| x.choose(&mut rng))
+            .copied()
+            .collect::<Vec<_>>();
+        while password.len() < *length as usize {
+            password.extend(all.choose(&mut rng));
+        }
+        password.shuffle(&mut rng);
+        Ok(String::from_utf8_lossy(&password).to_string())
+    }
+}
+
 const BW_CLIENTID: &str = "BW_CLIENTID";
 const BW_CLIENTSECRET: &str = "BW_CLIENTSECRET";
 const BW_PASSWORD: &str = "BW_PASSWORD";
+const BITWARDENCLI_APPDATA_DIR: &str = "BITWARDENCLI_APPDATA_DIR";
 
 #[async_trait]
 pub trait SecretStoreGetItem {
@@ -88,27 +281,84 @@ pub trait SecretStoreSynchronize {
 }
 
 impl BitwardenCliClient {
-    pub fn from_env() -> eyre::Result<Self> {
+    pub fn from_config(config: &Config) -> eyre::Result<Self> {
         Ok(BitwardenCliClient {
-            client_id: env::var(BW_CLIENTID)
-                .map_err(|_| MissingEnvVariable(BW_CLIENTID.to_string()))?
-                .to_string(),
-            client_secret: env::var(BW_CLIENTSECRET)
-                .map_err(|_| MissingEnvVariable(BW_CLIENTSECRET.to_string()))?
-                .to_string(),
-            client_password: env::var(BW_PASSWORD)
-                .map_err(|_| MissingEnvVariable(BW_PASSWORD.to_string()))?
-                .to_string(),
+            client_id: config
+                .bw_client_id
+                .clone()
+                .ok_or_else(|| MissingSetting(BW_CLIENTID.to_string()))?,
+            client_secret: config
+                .bw_client_secret
+                .clone()
+                .ok_or_else(|| MissingSetting(BW_CLIENTSECRET.to_string()))?,
+            client_password: config
+                .bw_password
+                .clone()
+                .ok_or_else(|| MissingSetting(BW_PASSWORD.to_string()))?,
+            appdata_dir: config.bitwardencli_appdata_dir.clone(),
+            local_items: None,
+            offline: false,
             storage: Arc::new(RwLock::new(BitwardenCliWrapperStorage::default())),
         })
     }
 
+    /// Client serving the items of a fixture, e.g. the output of `bw list items`, without a vault
+    pub fn from_fixture(items: Vec<BitwardenItem>) -> Self {
+        BitwardenCliClient {
+            client_id: String::new(),
+            client_secret: String::new(),
+            client_password: String::new(),
+            appdata_dir: None,
+            local_items: Some(Arc::new(RwLock::new(
+                items.into_iter().map(|x| (x.id.clone(), x)).collect(),
+            ))),
+            offline: true,
+            storage: Arc::new(RwLock::new(BitwardenCliWrapperStorage::default())),
+        }
+    }
+
+    /// Keeps the items created or edited in memory, the vault is only read
+    pub fn dry_run(mut self) -> Self {
+        self.local_items.get_or_insert_with(Default::default);
+        self
+    }
+
+    /// Stores the item in memory, returns it the way Bitwarden would
+    async fn write_local_item(
+        local_items: &RwLock<HashMap<String, BitwardenItem>>,
+        item_id: Option<&str>,
+        item: &BitwardenItem,
+    ) -> BitwardenItem {
+        let mut local_items = local_items.write().await;
+        let mut item = item.clone();
+        item.id = match item_id {
+            Some(x) => x.to_string(),
+            None => format!("local-{}", local_items.len() + 1),
+        };
+        item.revision_date = Some(Utc::now());
+        local_items.insert(item.id.clone(), item.clone());
+        item
+    }
+
+    /// `bw` command, using the configured data directory
+    fn command(&self) -> tokio::process::Command {
+        let mut command = tokio::process::Command::new("bw");
+        if let Some(appdata_dir) = &self.appdata_dir {
+            command.env(BITWARDENCLI_APPDATA_DIR, appdata_dir);
+        }
+        command
+    }
+
     pub async fn login(&self) -> eyre::Result<(), BitwardenError> {
+        if self.offline {
+            return Ok(());
+        }
         let client_id = self.client_id.clone();
         let client_secret = self.client_secret.clone();
 
         info!("`bw login`");
-        let output = tokio::process::Command::new("bw")
+        let output = self
+            .command()
             .args(["login", "--apikey", "--nointeraction"])
             .env(BW_CLIENTID, client_id)
             .env(BW_CLIENTSECRET, client_secret)
@@ -147,12 +397,16 @@ impl BitwardenCliClient {
     }
 
     pub async fn unlock(&self) -> Result<(), BitwardenError> {
+        if self.offline {
+            return Ok(());
+        }
         let client_id = self.client_id.clone();
         let client_secret = self.client_secret.clone();
         let client_password = self.client_password.clone();
 
         info!("`bw unlock`");
-        let cmd = tokio::process::Command::new("bw")
+        let cmd = self
+            .command()
             .args(["unlock", "--passwordenv", "BW_PASSWORD", "--nointeraction"])
             .env("BW_CLIENTID", client_id)
             .env("BW_CLIENTSECRET", client_secret)
@@ -183,25 +437,37 @@ impl BitwardenCliClient {
     }
 
     pub async fn sync(&self) -> Result<(), BitwardenError> {
+        if self.offline {
+            return Ok(());
+        }
         let mut storage = self.storage.write().await;
         let Some(session_token) = &storage.session_token else {
             return Err(BitwardenError::SyncFailedTokenMissing);
         };
 
         info!("`bw sync`");
-        let cmd = tokio::process::Command::new("bw")
+        let cmd = self
+            .command()
             .args(["sync"])
             .env("BW_SESSION", session_token.clone())
             .output()
             .await;
 
         match cmd {
-            Ok(_) => {
+            Ok(output) if output.status.success() => {
                 info!("`bw sync` succeed");
 
                 storage.last_sync = Some(chrono::offset::Utc::now());
                 Ok(())
             }
+            Ok(output) => {
+                error!(
+                    "`bw sync` failed, {}",
+                    String::from_utf8_lossy(&output.stderr)
+                );
+                storage.needs_relog = true;
+                Err(BitwardenError::SyncFailed)
+            }
             Err(err) => {
                 error!("`bw sync` failed, {}", err.to_string());
                 storage.needs_relog = true;
@@ -210,13 +476,68 @@ impl BitwardenCliClient {
         }
     }
 
+    /// Locks the vault and forgets the session token, the CLI has to be unlocked again before use
+    pub async fn lock(&self) -> Result<(), BitwardenError> {
+        if self.offline {
+            return Ok(());
+        }
+        let mut storage = self.storage.write().await;
+        let Some(session_token) = storage.session_token.take() else {
+            return Ok(());
+        };
+
+        info!("`bw lock`");
+        let output = self
+            .command()
+            .args(["lock"])
+            .env("BW_SESSION", session_token)
+            .output()
+            .await?;
+        if !output.status.success() {
+            return Err(BitwardenError::LockFailed(
+                String::from_utf8_lossy(&output.stderr).to_string(),
+            ));
+        }
+        info!("`bw lock` succeed");
+        Ok(())
+    }
+
+    /// Logs out of the vault, the cached vault data is dropped by the CLI
+    pub async fn logout(&self) -> Result<(), BitwardenError> {
+        if self.offline {
+            return Ok(());
+        }
+        info!("`bw logout`");
+        let output = self
+            .command()
+            .args(["logout", "--nointeraction"])
+            .output()
+            .await?;
+        if !output.status.success() {
+            return Err(BitwardenError::LogoutFailed(
+                String::from_utf8_lossy(&output.stderr).to_string(),
+            ));
+        }
+        info!("`bw logout` succeed");
+        Ok(())
+    }
+
     pub async fn get_item(&self, item_id: String) -> Result<BitwardenItem, BitwardenError> {
+        if let Some(local_items) = &self.local_items {
+            if let Some(item) = local_items.read().await.get(&item_id) {
+                return Ok(item.clone());
+            }
+            if self.offline {
+                return Err(BitwardenError::ItemNotFound(item_id));
+            }
+        }
         let mut storage = self.storage.write().await;
         let Some(session_token) = &storage.session_token else {
             return Err(BitwardenError::SyncFailedTokenMissing);
         };
 
-        let cmd = tokio::process::Command::new("bw")
+        let cmd = self
+            .command()
             .args(["--response", "get", "item", &item_id, "--nointeraction"])
             .env("BW_SESSION", session_token)
             .output()
@@ -227,18 +548,18 @@ impl BitwardenCliClient {
                 let response =
                     serde_json::from_slice::<BitwardenGetItemResponse>(output.stdout.as_slice());
 
-                if response.is_err() {
-                    let error_msg = String::from_utf8(output.stdout).unwrap();
-                    error!(
-                        "`bw get item {}` failed: {}, body: {}",
-                        item_id,
-                        response.unwrap_err(),
-                        error_msg
-                    );
-                    return Err(BitwardenError::ItemNotFound(item_id));
-                }
+                let data = match response {
+                    Ok(data) => data,
+                    Err(err) => {
+                        let error_msg = String::from_utf8(output.stdout).unwrap();
+                        error!(
+                            "`bw get item {}` failed: {}, body: {}",
+                            item_id, err, error_msg
+                        );
+                        return Err(BitwardenError::ItemNotFound(item_id));
+                    }
+                };
 
-                let data = response.unwrap();
                 if !data.success {
                     error!("`bw get item {}` failed, couldn't find item", item_id);
                     return Err(BitwardenError::ItemNotFound(item_id));
@@ -260,11 +581,193 @@ impl BitwardenCliClient {
             }
         }
     }
+
+    /// Returns the items named exactly `name`, `bw list items --search` also matches partially
+    pub async fn find_items_by_name(
+        &self,
+        name: &str,
+    ) -> Result<Vec<BitwardenItem>, BitwardenError> {
+        if let Some(local_items) = &self.local_items {
+            let found = local_items
+                .read()
+                .await
+                .values()
+                .filter(|x| x.name.as_deref() == Some(name))
+                .cloned()
+                .collect::<Vec<_>>();
+            if self.offline || !found.is_empty() {
+                return Ok(found);
+            }
+        }
+        let mut storage = self.storage.write().await;
+        let Some(session_token) = &storage.session_token else {
+            return Err(BitwardenError::SyncFailedTokenMissing);
+        };
+
+        let cmd = self
+            .command()
+            .args([
+                "--response",
+                "list",
+                "items",
+                "--search",
+                name,
+                "--nointeraction",
+            ])
+            .env("BW_SESSION", session_token)
+            .output()
+            .await;
+
+        let output = match cmd {
+            Ok(output) => output,
+            Err(err) => {
+                error!("`bw list items --search {}` failed, {}", name, err);
+                storage.needs_relog = true;
+                return Err(BitwardenError::ListItemsFailed(
+                    name.to_string(),
+                    err.to_string(),
+                ));
+            }
+        };
+        match serde_json::from_slice::<BitwardenListItemsResponse>(output.stdout.as_slice()) {
+            Ok(BitwardenListItemsResponse {
+                success: true,
+                data: Some(list),
+            }) => Ok(list
+                .data
+                .into_iter()
+                .filter(|x| x.name.as_deref() == Some(name))
+                .collect()),
+            _ => {
+                let body = String::from_utf8_lossy(&output.stdout).to_string();
+                error!("`bw list items --search {}` failed, body: {}", name, body);
+                Err(BitwardenError::ListItemsFailed(name.to_string(), body))
+            }
+        }
+    }
+
+    /// Generates a password or a passphrase, it is not stored anywhere. Offline clients generate
+    /// passwords locally and can't generate passphrases.
+    pub async fn generate(&self, generator: &BitwardenGenerator) -> Result<String, BitwardenError> {
+        if self.offline {
+            return generator.generate_locally();
+        }
+        let output = self
+            .command()
+            .arg("generate")
+            .args(generator.args())
+            .arg("--nointeraction")
+            .output()
+            .await?;
+
+        let value = String::from_utf8(output.stdout)
+            .map_err(|_| BitwardenError::GenerateFailed("output is not UTF-8".to_string()))?;
+        if !output.status.success() || value.is_empty() {
+            let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+            error!("`bw generate` failed: {}", stderr);
+            return Err(BitwardenError::GenerateFailed(stderr));
+        }
+        Ok(value)
+    }
+
+    /// Creates the item, returns it as stored by Bitwarden, with its `id` and `revisionDate`
+    pub async fn create_item(&self, item: &BitwardenItem) -> Result<BitwardenItem, BitwardenError> {
+        if let Some(local_items) = &self.local_items {
+            return Ok(Self::write_local_item(local_items, None, item).await);
+        }
+        let name = item.name.clone().unwrap_or_default();
+        info!("`bw create item {name}`");
+        self.write_item(&["create", "item"], item)
+            .await
+            .map_err(|err| {
+                error!("`bw create item {}` failed: {}", name, err);
+                BitwardenError::CreateItemFailed(err)
+            })
+    }
+
+    /// Replaces the item, returns it as stored by Bitwarden, with its new `revisionDate`
+    pub async fn edit_item(
+        &self,
+        item_id: &str,
+        item: &BitwardenItem,
+    ) -> Result<BitwardenItem, BitwardenError> {
+        if let Some(local_items) = &self.local_items {
+            return Ok(Self::write_local_item(local_items, Some(item_id), item).await);
+        }
+        info!("`bw edit item {item_id}`");
+        self.write_item(&["edit", "item", item_id], item)
+            .await
+            .map_err(|err| {
+                error!("`bw edit item {}` failed: {}", item_id, err);
+                BitwardenError::EditItemFailed(item_id.to_string(), err)
+            })
+    }
+
+    /// Runs `bw create`/`bw edit` with the base64 encoded item, the same way `bw encode` does. The
+    /// item is written to stdin, the arguments of a process can be read by any other one.
+    async fn write_item(
+        &self,
+        args: &[&str],
+        item: &BitwardenItem,
+    ) -> Result<BitwardenItem, String> {
+        let mut storage = self.storage.write().await;
+        let Some(session_token) = &storage.session_token else {
+            return Err(BitwardenError::SyncFailedTokenMissing.to_string());
+        };
+
+        let encoded = serde_json::to_vec(item)
+            .map(|x| base64::engine::general_purpose::STANDARD.encode(x))
+            .map_err(|e| e.to_string())?;
+        let child = self
+            .command()
+            .arg("--response")
+            .args(args)
+            .arg("--nointeraction")
+            .env("BW_SESSION", session_token)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+        let cmd = match child {
+            Ok(mut child) => {
+                let mut stdin = child.stdin.take().expect("stdin is piped");
+                // stdin is closed once written, bw reads it until the end
+                match stdin.write_all(encoded.as_bytes()).await {
+                    Ok(()) => {
+                        drop(stdin);
+                        child.wait_with_output().await
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            Err(err) => Err(err),
+        };
+
+        let output = match cmd {
+            Ok(output) => output,
+            Err(err) => {
+                storage.needs_relog = true;
+                return Err(err.to_string());
+            }
+        };
+        match serde_json::from_slice::<BitwardenGetItemResponse>(output.stdout.as_slice()) {
+            Ok(BitwardenGetItemResponse {
+                success: true,
+                data: Some(item),
+            }) => Ok(item),
+            Ok(_) => Err(String::from_utf8_lossy(&output.stdout).to_string()),
+            Err(err) => Err(format!(
+                "{}, body: {}",
+                err,
+                String::from_utf8_lossy(&output.stdout)
+            )),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::bitwarden_cli::BitwardenItem;
+    use crate::bitwarden_cli::{BitwardenGenerator, BitwardenItem};
     use std::fs;
 
     const BITWARDEN_FIELDS: &str = "tests/bitwarden-fields.json";
@@ -286,6 +789,44 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn bitwarden_item_round_trip_keeps_unknown_properties() -> eyre::Result<()> {
+        let json = fs::read_to_string(BITWARDEN_FIELDS)?;
+        let bitwarden_item: BitwardenItem = serde_json::from_str(&json)?;
+        assert!(bitwarden_item.revision_date.is_some());
+        assert_eq!(bitwarden_item.item_type, Some(2));
+
+        let mut expected: serde_json::Value = serde_json::from_str(&json)?;
+        // chrono drops the zero milliseconds
+        expected["revisionDate"] = "2024-01-01T00:00:00Z".into();
+        assert_eq!(serde_json::to_value(&bitwarden_item)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn generator_args() {
+        let password = BitwardenGenerator::Password {
+            length: 32,
+            uppercase: true,
+            lowercase: true,
+            numbers: false,
+            special: true,
+        };
+        assert_eq!(
+            password.args(),
+            ["--length", "32", "--uppercase", "--lowercase", "--special"]
+        );
+
+        let passphrase = BitwardenGenerator::Passphrase {
+            words: 5,
+            separator: "-".to_string(),
+        };
+        assert_eq!(
+            passphrase.args(),
+            ["--passphrase", "--words", "5", "--separator", "-"]
+        );
+    }
+
     #[test]
     fn deserialize_bitwarden_notes() -> eyre::Result<()> {
         let bitwarden_item = fs::read_to_string(BITWARDEN_NOTES)
@@ -0,0 +1,244 @@
+use crate::render::RenderArgs;
+use clap::error::ErrorKind;
+use clap::{Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+const REDACTED: &str = "<redacted>";
+
+/// Configuration of the operator, merged from the command line flags, the environment variables
+/// and the YAML configuration file, in that order of precedence.
+///
+/// Every setting is optional here, defaults are applied and values validated when the typed
+/// settings of each component are built from it.
+#[derive(Debug, Clone, Default, Parser, Serialize, Deserialize)]
+#[command(version, about = "Syncs Kubernetes Secrets with a Bitwarden vault", long_about = None)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Config {
+    /// YAML file holding the configuration, e.g. `fieldManager: my-operator`
+    #[arg(long, env = "CONFIG_FILE")]
+    #[serde(skip)]
+    pub config_file: Option<PathBuf>,
+    /// Prints the configuration, secrets redacted, and exits
+    #[arg(long)]
+    #[serde(skip)]
+    pub print_config: bool,
+    /// Runs a subcommand instead of the operator
+    #[command(subcommand)]
+    #[serde(skip)]
+    pub command: Option<Command>,
+
+    #[arg(long, env = "BW_CLIENTID", hide_env_values = true)]
+    pub bw_client_id: Option<String>,
+    #[arg(long, env = "BW_CLIENTSECRET", hide_env_values = true)]
+    pub bw_client_secret: Option<String>,
+    #[arg(long, env = "BW_PASSWORD", hide_env_values = true)]
+    pub bw_password: Option<String>,
+    /// How often `bw sync` is called, `60s` by default
+    #[arg(long, env = "BW_SYNC_INTERVAL")]
+    pub bw_sync_interval: Option<String>,
+    /// Data directory of the Bitwarden CLI
+    #[arg(long, env = "BITWARDENCLI_APPDATA_DIR")]
+    pub bitwardencli_appdata_dir: Option<PathBuf>,
+
+    /// Address of the /metrics and /health server, `127.0.0.1:3001` by default
+    #[arg(long, env = "METRICS_ENDPOINT")]
+    pub metrics_endpoint: Option<String>,
+    /// OTLP endpoint the traces are exported to
+    #[arg(long, env = "OPENTELEMETRY_ENDPOINT_URL")]
+    pub opentelemetry_endpoint_url: Option<String>,
+
+    /// Identity of the replica
+    #[arg(long, env = "POD_NAME")]
+    pub pod_name: Option<String>,
+    /// Namespace of the operator
+    #[arg(long, env = "POD_NAMESPACE")]
+    pub pod_namespace: Option<String>,
+
+    /// Server-side apply field manager, `bitwarden-secret-operator` by default
+    #[arg(long, env = "FIELD_MANAGER")]
+    pub field_manager: Option<String>,
+    /// `Force` (default) takes over conflicting fields, `Fail` aborts the write
+    #[arg(long, env = "APPLY_CONFLICT_POLICY")]
+    pub apply_conflict_policy: Option<String>,
+
+    /// Default `refreshInterval` of BitwardenSecrets, `1h` by default
+    #[arg(long, env = "DEFAULT_REFRESH_INTERVAL")]
+    pub default_refresh_interval: Option<String>,
+    /// Default `refreshSchedule` of BitwardenSecrets
+    #[arg(long, env = "DEFAULT_REFRESH_SCHEDULE")]
+    pub default_refresh_schedule: Option<String>,
+
+    /// Watched namespaces, every namespace by default
+    #[arg(long, env = "WATCH_NAMESPACES", value_delimiter = ',')]
+    pub watch_namespaces: Option<Vec<String>>,
+    /// Label selector of the watched namespaces
+    #[arg(long, env = "WATCH_NAMESPACE_SELECTOR")]
+    pub watch_namespace_selector: Option<String>,
+    /// Label selector of the watched BitwardenSecrets and BitwardenPushSecrets
+    #[arg(long, env = "WATCH_LABEL_SELECTOR")]
+    pub watch_label_selector: Option<String>,
+
+    #[arg(long, env = "LEADER_ELECTION")]
+    pub leader_election: Option<bool>,
+    #[arg(long, env = "LEADER_ELECTION_LEASE_NAME")]
+    pub leader_election_lease_name: Option<String>,
+    #[arg(long, env = "LEADER_ELECTION_LEASE_DURATION")]
+    pub leader_election_lease_duration: Option<String>,
+    #[arg(long, env = "LEADER_ELECTION_RETRY_PERIOD")]
+    pub leader_election_retry_period: Option<String>,
+
+    #[arg(long, env = "SHARDING")]
+    pub sharding: Option<bool>,
+    #[arg(long, env = "SHARDING_LEASE_PREFIX")]
+    pub sharding_lease_prefix: Option<String>,
+    #[arg(long, env = "SHARDING_LEASE_DURATION")]
+    pub sharding_lease_duration: Option<String>,
+    #[arg(long, env = "SHARDING_RETRY_PERIOD")]
+    pub sharding_retry_period: Option<String>,
+
+    /// Address of the webhook server, `0.0.0.0:8443` by default
+    #[arg(long, env = "WEBHOOK_ENDPOINT")]
+    pub webhook_endpoint: Option<String>,
+    #[arg(long, env = "WEBHOOK_TLS_CERT")]
+    pub webhook_tls_cert: Option<PathBuf>,
+    #[arg(long, env = "WEBHOOK_TLS_KEY")]
+    pub webhook_tls_key: Option<PathBuf>,
+    #[arg(long, env = "WEBHOOK_TLS_CA")]
+    pub webhook_tls_ca: Option<PathBuf>,
+    #[arg(long, env = "WEBHOOK_SELF_MANAGED_TLS")]
+    pub webhook_self_managed_tls: Option<bool>,
+    #[arg(long, env = "WEBHOOK_VERIFY_VAULT")]
+    pub webhook_verify_vault: Option<bool>,
+    #[arg(long, env = "WEBHOOK_SERVICE_NAME")]
+    pub webhook_service_name: Option<String>,
+    #[arg(long, env = "WEBHOOK_CERT_SECRET_NAME")]
+    pub webhook_cert_secret_name: Option<String>,
+    #[arg(long, env = "WEBHOOK_CONFIGURATION_NAME")]
+    pub webhook_configuration_name: Option<String>,
+
+    /// How long in-flight reconciles are given to finish on shutdown, `30s` by default
+    #[arg(long, env = "SHUTDOWN_TIMEOUT")]
+    pub shutdown_timeout: Option<String>,
+    #[arg(long, env = "BW_LOGOUT_ON_EXIT")]
+    pub bw_logout_on_exit: Option<bool>,
+    #[arg(long, env = "BW_WIPE_APPDATA_ON_EXIT")]
+    pub bw_wipe_appdata_on_exit: Option<bool>,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    Render(RenderArgs),
+}
+
+impl Config {
+    /// Parses the command line and the environment, then fills the settings they don't set from
+    /// the configuration file
+    pub fn load() -> eyre::Result<Self> {
+        let config = Config::try_parse().map_err(|e| match e.kind() {
+            ErrorKind::DisplayHelp | ErrorKind::DisplayVersion => e.exit(),
+            _ => e,
+        })?;
+        let Some(path) = &config.config_file else {
+            return Ok(config);
+        };
+        let file = std::fs::read_to_string(path)
+            .map_err(|e| eyre::eyre!("{} couldn't be read: {e}", path.display()))?;
+        let file: Config = serde_yaml::from_str(&file)
+            .map_err(|e| eyre::eyre!("{} is invalid: {e}", path.display()))?;
+        config.merge(file)
+    }
+
+    /// Settings set in `self` take precedence over the ones of `other`
+    fn merge(self, other: Config) -> eyre::Result<Self> {
+        let mut merged = serde_json::to_value(&other)?;
+        if let (Some(merged), serde_json::Value::Object(values)) =
+            (merged.as_object_mut(), serde_json::to_value(&self)?)
+        {
+            merged.extend(values.into_iter().filter(|x| !x.1.is_null()));
+        }
+        Ok(Self {
+            config_file: self.config_file,
+            print_config: self.print_config,
+            command: self.command,
+            ..serde_json::from_value(merged)?
+        })
+    }
+
+    /// YAML of the settings which are set, with the credentials redacted
+    pub fn redacted(&self) -> eyre::Result<String> {
+        let mut config = self.clone();
+        for secret in [
+            &mut config.bw_client_id,
+            &mut config.bw_client_secret,
+            &mut config.bw_password,
+        ] {
+            if secret.is_some() {
+                *secret = Some(REDACTED.to_string());
+            }
+        }
+        let mut values = serde_json::to_value(&config)?;
+        if let Some(values) = values.as_object_mut() {
+            values.retain(|_, x| !x.is_null());
+        }
+        Ok(serde_yaml::to_string(&values)?)
+    }
+}
+
+/// Settings which can change at runtime, shared with the components reading them
+#[derive(Debug, Clone, Default)]
+pub struct Reloadable<T>(Arc<RwLock<T>>);
+
+impl<T: Clone> Reloadable<T> {
+    pub fn new(value: T) -> Self {
+        Self(Arc::new(RwLock::new(value)))
+    }
+
+    pub fn get(&self) -> T {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn set(&self, value: T) {
+        *self.0.write().unwrap() = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+
+    #[test]
+    fn flags_and_env_take_precedence_over_the_file() {
+        let file: Config = serde_yaml::from_str(
+            "fieldManager: from-file\nshutdownTimeout: 10s\nwatchNamespaces: [team-a, team-b]",
+        )
+        .unwrap();
+        let config = Config {
+            field_manager: Some("from-env".to_string()),
+            ..Default::default()
+        }
+        .merge(file)
+        .unwrap();
+        assert_eq!(config.field_manager.as_deref(), Some("from-env"));
+        assert_eq!(config.shutdown_timeout.as_deref(), Some("10s"));
+        assert_eq!(
+            config.watch_namespaces,
+            Some(vec!["team-a".to_string(), "team-b".to_string()])
+        );
+    }
+
+    #[test]
+    fn credentials_are_redacted() {
+        let config = Config {
+            bw_password: Some("hunter2".to_string()),
+            field_manager: Some("operator".to_string()),
+            ..Default::default()
+        };
+        let redacted = config.redacted().unwrap();
+        assert!(!redacted.contains("hunter2"));
+        assert!(redacted.contains("bwPassword: <redacted>"));
+        assert!(redacted.contains("fieldManager: operator"));
+        assert!(!redacted.contains("bwClientId"));
+    }
+}
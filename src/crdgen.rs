@@ -1,13 +1,10 @@
-pub mod bitwarden_cli;
-pub mod monitoring;
-pub mod operator;
-
-use crate::operator::schemas::BitwardenSecret;
+use bitwarden_operator_rs::operator::schemas::{bitwarden_secret_crd, BitwardenPushSecret};
 use kube::CustomResourceExt;
 
 fn main() {
     print!(
-        "{}",
-        serde_yaml::to_string(&BitwardenSecret::crd()).unwrap()
+        "{}---\n{}",
+        serde_yaml::to_string(&bitwarden_secret_crd()).unwrap(),
+        serde_yaml::to_string(&BitwardenPushSecret::crd()).unwrap()
     )
 }
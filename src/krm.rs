@@ -0,0 +1,261 @@
+use bitwarden_operator_rs::bitwarden_cli::BitwardenCliClient;
+use bitwarden_operator_rs::config::Config;
+use bitwarden_operator_rs::render::{
+    is_bitwarden_secret, parse_bitwarden_secret, render_client, render_secret,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Annotations set by the orchestrator, e.g. the file the item comes from, kept on the Secret
+const ORCHESTRATOR_ANNOTATIONS: [&str; 2] =
+    ["config.kubernetes.io/", "internal.config.kubernetes.io/"];
+
+/// Input and output of a KRM function
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ResourceList {
+    api_version: String,
+    kind: String,
+    #[serde(default)]
+    items: Vec<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    function_config: Option<Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    results: Vec<KrmResult>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KrmResult {
+    message: String,
+    severity: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    resource_ref: Option<ResourceRef>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ResourceRef {
+    api_version: String,
+    kind: String,
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    namespace: Option<String>,
+}
+
+/// Settings read from the `data` of the function config, e.g. a ConfigMap
+#[derive(Debug, Default, Deserialize)]
+struct FunctionConfig {
+    /// JSON array of Bitwarden items read instead of the vault
+    fixture: Option<PathBuf>,
+    /// namespace of the BitwardenSecrets which don't set one
+    namespace: Option<String>,
+}
+
+impl FunctionConfig {
+    fn from_resource_list(list: &ResourceList) -> eyre::Result<Self> {
+        let data = list
+            .function_config
+            .as_ref()
+            .and_then(|x| x.get("data").or_else(|| x.get("spec")));
+        match data {
+            Some(data) => Ok(serde_json::from_value(data.clone())?),
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+fn error(message: String, item: Option<&Value>) -> KrmResult {
+    let field = |item: &Value, path: &[&str]| {
+        path.iter()
+            .try_fold(item, |x, key| x.get(key))
+            .and_then(|x| x.as_str())
+            .map(String::from)
+    };
+    KrmResult {
+        message,
+        severity: "error".to_string(),
+        resource_ref: item.map(|item| ResourceRef {
+            api_version: field(item, &["apiVersion"]).unwrap_or_default(),
+            kind: field(item, &["kind"]).unwrap_or_default(),
+            name: field(item, &["metadata", "name"]).unwrap_or_default(),
+            namespace: field(item, &["metadata", "namespace"]),
+        }),
+    }
+}
+
+/// Renders the Secret replacing the BitwardenSecret, with the annotations of the orchestrator
+async fn render_item(
+    cli: Arc<BitwardenCliClient>,
+    item: &Value,
+    namespace: Option<&str>,
+) -> eyre::Result<Value> {
+    let bitwarden_secret = parse_bitwarden_secret(item.clone())?;
+    // the Secret is left without namespace when the BitwardenSecret has none, as any other item
+    let keep_namespace = namespace.is_some()
+        || bitwarden_secret.metadata.namespace.is_some()
+        || bitwarden_secret.spec.namespace.is_some();
+    let mut secret = render_secret(
+        cli,
+        bitwarden_secret.clone(),
+        namespace.unwrap_or("default"),
+    )
+    .await?;
+    if !keep_namespace {
+        secret.metadata.namespace = None;
+    }
+
+    let annotations = secret
+        .metadata
+        .annotations
+        .get_or_insert_with(Default::default);
+    for (key, value) in bitwarden_secret.metadata.annotations.iter().flatten() {
+        if ORCHESTRATOR_ANNOTATIONS.iter().any(|x| key.starts_with(x)) {
+            annotations.insert(key.clone(), value.clone());
+        }
+    }
+    Ok(serde_json::to_value(secret)?)
+}
+
+/// Replaces the BitwardenSecrets of the list by their Secrets, the ones which can't be rendered
+/// are left as they are and reported in the results
+async fn process(mut list: ResourceList, config: &Config) -> ResourceList {
+    if !list.items.iter().any(is_bitwarden_secret) {
+        return list;
+    }
+    let function_config = match FunctionConfig::from_resource_list(&list) {
+        Ok(x) => x,
+        Err(e) => {
+            list.results
+                .push(error(format!("invalid functionConfig: {e}"), None));
+            return list;
+        }
+    };
+    let cli = match render_client(config, function_config.fixture.as_deref()).await {
+        Ok(x) => x,
+        Err(e) => {
+            list.results.push(error(e.to_string(), None));
+            return list;
+        }
+    };
+
+    let mut results = vec![];
+    for item in list.items.iter_mut().filter(|x| is_bitwarden_secret(x)) {
+        match render_item(cli.clone(), item, function_config.namespace.as_deref()).await {
+            Ok(secret) => *item = secret,
+            Err(e) => results.push(error(e.to_string(), Some(item))),
+        }
+    }
+    list.results.extend(results);
+    list
+}
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    // stdout only holds the resource list
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(tracing::Level::WARN)
+        .init();
+
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input)?;
+    let list: ResourceList = serde_yaml::from_str(&input)?;
+    let config = Config::load()?;
+
+    let list = process(list, &config).await;
+    print!("{}", serde_yaml::to_string(&list)?);
+    if list.results.iter().any(|x| x.severity == "error") {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{process, ResourceList};
+    use bitwarden_operator_rs::config::Config;
+    use std::io::Write;
+
+    #[tokio::test]
+    async fn bitwarden_secrets_are_replaced_by_secrets() {
+        let mut fixture = tempfile();
+        write!(
+            fixture.1,
+            "[{}]",
+            std::fs::read_to_string("tests/bitwarden-fields.json").unwrap()
+        )
+        .unwrap();
+        let list: ResourceList = serde_yaml::from_str(&format!(
+            r#"
+apiVersion: config.kubernetes.io/v1
+kind: ResourceList
+functionConfig:
+  apiVersion: v1
+  kind: ConfigMap
+  data:
+    fixture: {}
+items:
+- apiVersion: v1
+  kind: ConfigMap
+  metadata:
+    name: untouched
+- apiVersion: bitwarden-secret-operator.io/v1
+  kind: BitwardenSecret
+  metadata:
+    name: rendered
+    annotations:
+      config.kubernetes.io/index: "0"
+  spec:
+    bitwardenId: 00000000-0000-0000-0000-000000000000
+    content:
+    - kubernetesSecretKey: FIELD
+      source:
+        field:
+          name: super-secret-field
+- apiVersion: bitwarden-secret-operator.io/v1
+  kind: BitwardenSecret
+  metadata:
+    name: failing
+    namespace: team-a
+  spec:
+    bitwardenId: missing
+    content:
+    - kubernetesSecretKey: NOTE
+      source:
+        note: {{}}
+"#,
+            fixture.0.display()
+        ))
+        .unwrap();
+
+        let list = process(list, &Config::default()).await;
+        assert_eq!(list.items[0]["kind"], "ConfigMap");
+        assert_eq!(list.items[1]["kind"], "Secret");
+        assert_eq!(list.items[1]["metadata"]["name"], "rendered");
+        assert!(list.items[1]["metadata"].get("namespace").is_none());
+        assert_eq!(
+            list.items[1]["metadata"]["annotations"]["config.kubernetes.io/index"],
+            "0"
+        );
+        assert_eq!(list.items[1]["data"]["FIELD"], "c3VwZXItc2VjcmV0");
+        assert_eq!(list.items[2]["kind"], "BitwardenSecret");
+
+        assert_eq!(list.results.len(), 1);
+        let result = &list.results[0];
+        assert_eq!(result.severity, "error");
+        let resource_ref = result.resource_ref.as_ref().unwrap();
+        assert_eq!(resource_ref.name, "failing");
+        assert_eq!(resource_ref.namespace.as_deref(), Some("team-a"));
+        std::fs::remove_file(fixture.0).unwrap();
+    }
+
+    fn tempfile() -> (std::path::PathBuf, std::fs::File) {
+        let path = std::env::temp_dir().join(format!("krm-fixture-{}.json", std::process::id()));
+        let file = std::fs::File::create(&path).unwrap();
+        (path, file)
+    }
+}
@@ -0,0 +1,6 @@
+pub mod bitwarden_cli;
+pub mod config;
+pub mod monitoring;
+pub mod operator;
+pub mod render;
+pub mod shutdown;
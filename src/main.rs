@@ -2,21 +2,21 @@ use axum::routing::get;
 use axum::Router;
 use kube::Client;
 use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
-use std::env;
 use std::future::ready;
 use std::sync::Arc;
-use tokio::join;
-use tracing::info;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::{select, try_join};
+use tracing::{info, warn};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{filter, Layer};
 
-use crate::bitwarden_cli::BitwardenCliClient;
-use crate::operator::controller::BitwardenOperator;
-
-pub mod bitwarden_cli;
-pub mod monitoring;
-pub mod operator;
+use bitwarden_operator_rs::bitwarden_cli::BitwardenCliClient;
+use bitwarden_operator_rs::config::{Command, Config};
+use bitwarden_operator_rs::operator::controller::{BitwardenOperator, OperatorSettings};
+use bitwarden_operator_rs::operator::webhook::{start_webhook_server, WebhookSettings};
+use bitwarden_operator_rs::shutdown::{close_vault, Shutdown, ShutdownSettings};
+use bitwarden_operator_rs::{monitoring, render};
 
 fn setup_metrics_recorder() -> PrometheusHandle {
     PrometheusBuilder::new().install_recorder().unwrap()
@@ -26,14 +26,12 @@ async fn health() -> &'static str {
     "Hello, World!"
 }
 
-async fn start_metrics_server() {
+async fn start_metrics_server(metrics_endpoint: String) {
     let recorder_handle = setup_metrics_recorder();
     let app = Router::new()
         .route("/metrics", get(move || ready(recorder_handle.render())))
         .route("/health", get(health));
 
-    let metrics_endpoint =
-        env::var("METRICS_ENDPOINT").unwrap_or_else(|_| "127.0.0.1:3001".to_string());
     let listener = tokio::net::TcpListener::bind(metrics_endpoint)
         .await
         .unwrap();
@@ -44,12 +42,44 @@ async fn start_metrics_server() {
     axum::serve(listener, app).await.unwrap();
 }
 
+/// Reloads the configuration on SIGHUP, only the settings which can change at runtime are applied
+async fn reload_on_sighup(operator: &BitwardenOperator) -> eyre::Result<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    while hangup.recv().await.is_some() {
+        info!("SIGHUP received, reloading the configuration...");
+        match Config::load().and_then(|x| OperatorSettings::from_config(&x)) {
+            Ok(settings) => operator.reload(&settings),
+            Err(e) => warn!("invalid configuration, keeping the current one: {}", e),
+        }
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
+    let config = Config::load()?;
+    if config.print_config {
+        print!("{}", config.redacted()?);
+        return Ok(());
+    }
+    if let Some(Command::Render(args)) = &config.command {
+        // stdout only holds the rendered manifests
+        tracing_subscriber::fmt()
+            .with_writer(std::io::stderr)
+            .with_max_level(tracing::Level::WARN)
+            .init();
+        return render::run(&config, args).await;
+    }
+    // every setting is validated before anything starts
+    let cli = Arc::new(BitwardenCliClient::from_config(&config)?);
+    let settings = OperatorSettings::from_config(&config)?;
+    let webhook_settings = WebhookSettings::from_config(&config)?;
+    let shutdown_settings = ShutdownSettings::from_config(&config)?;
+
     let stdout_log = tracing_subscriber::fmt::layer();
 
     let stderr_log = tracing_subscriber::fmt::layer();
-    let tracer = monitoring::init_tracer().await;
+    let tracer = monitoring::init_tracer(config.opentelemetry_endpoint_url.clone()).await;
 
     let registry = tracing_subscriber::Registry::default()
         .with(stdout_log.with_filter(filter::LevelFilter::INFO))
@@ -62,14 +92,55 @@ async fn main() -> eyre::Result<()> {
         registry.init();
     }
 
-    let cli = Arc::new(BitwardenCliClient::from_env()?);
+    let shutdown = Shutdown::on_signal()?;
     cli.login().await?;
     cli.unlock().await?;
     cli.sync().await?;
 
     let client = Client::try_default().await?;
 
-    let bitwarden_operator = BitwardenOperator::new(cli, client);
-    let (_operator, _metrics_server) = join!(bitwarden_operator.start(), start_metrics_server());
-    Ok(())
+    let webhook_server = async {
+        match webhook_settings {
+            Some(webhook_settings) => {
+                start_webhook_server(webhook_settings, cli.clone(), client.clone()).await
+            }
+            None => Ok(()),
+        }
+    };
+
+    let bitwarden_operator = BitwardenOperator::new(cli.clone(), client.clone(), settings);
+    let metrics_endpoint = config
+        .metrics_endpoint
+        .unwrap_or_else(|| "127.0.0.1:3001".to_string());
+    let servers = async {
+        try_join!(
+            async {
+                start_metrics_server(metrics_endpoint).await;
+                Ok(())
+            },
+            webhook_server
+        )
+        .map(|_| ())
+    };
+    let deadline = async {
+        shutdown.clone().requested().await;
+        tokio::time::sleep(shutdown_settings.timeout).await;
+    };
+    // the process exits when the operator stops, e.g. when it loses the leadership or once the
+    // in-flight reconciles are drained after SIGTERM
+    let result = select! {
+        result = bitwarden_operator.start(shutdown.clone()) => result,
+        result = servers => result,
+        result = reload_on_sighup(&bitwarden_operator) => result,
+        _ = deadline => {
+            warn!("in-flight reconciles didn't finish within {:?}", shutdown_settings.timeout);
+            Ok(())
+        }
+    };
+
+    close_vault(&cli, &shutdown_settings).await;
+    // flushes the pending spans
+    let _ = tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await;
+    info!("Operator stopped");
+    result
 }
@@ -1,9 +1,9 @@
 use tracing::info;
 
-pub(crate) async fn init_tracer() -> Option<opentelemetry_sdk::trace::Tracer> {
-    let Ok(otlp_endpoint) = std::env::var("OPENTELEMETRY_ENDPOINT_URL") else {
-        return None;
-    };
+pub async fn init_tracer(
+    otlp_endpoint: Option<String>,
+) -> Option<opentelemetry_sdk::trace::Tracer> {
+    let otlp_endpoint = otlp_endpoint?;
 
     info!("Initializing OpenTelemetry Traces client");
     let channel = tonic::transport::Channel::from_shared(otlp_endpoint)
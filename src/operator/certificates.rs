@@ -0,0 +1,378 @@
+use crate::config::Config;
+use crate::operator::controller::DEFAULT_FIELD_MANAGER;
+use crate::operator::schemas::{BitwardenSecret, OPERATOR_MANAGED_BY, OPERATOR_MANAGED_BY_LABEL};
+use chrono::{DateTime, Datelike, Utc};
+use k8s_openapi::api::admissionregistration::v1::{
+    MutatingWebhookConfiguration, ValidatingWebhookConfiguration,
+};
+use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use k8s_openapi::ByteString;
+use kube::api::{Patch, PatchParams, PostParams};
+use kube::{Api, Client, CustomResourceExt};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyUsagePurpose,
+};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tracing::info;
+
+const TLS_CERT_KEY: &str = "tls.crt";
+const TLS_KEY_KEY: &str = "tls.key";
+const CA_CERT_KEY: &str = "ca.crt";
+const NOT_AFTER_ANNOTATION: &str = "bitwarden-secret-operator.io/not-after";
+const PEM_CERTIFICATE_END: &str = "-----END CERTIFICATE-----";
+
+/// Settings of the self-managed webhook serving certificate
+#[derive(Debug, Clone)]
+pub struct CertificateSettings {
+    /// namespace of the operator, where the certificate Secret is stored
+    pub namespace: String,
+    pub secret_name: String,
+    /// name of the Service in front of the webhook server
+    pub service_name: String,
+    /// name of the Validating/MutatingWebhookConfigurations to inject the CA bundle into
+    pub webhook_configuration_name: String,
+    pub validity: Duration,
+    /// the certificate is rotated when it expires in less than this
+    pub renew_before: Duration,
+}
+
+impl CertificateSettings {
+    pub fn from_config(config: &Config) -> eyre::Result<Self> {
+        Ok(Self {
+            namespace: config
+                .pod_namespace
+                .clone()
+                .ok_or_else(|| eyre::eyre!("self-managed TLS requires POD_NAMESPACE"))?,
+            secret_name: config
+                .webhook_cert_secret_name
+                .clone()
+                .unwrap_or_else(|| format!("{DEFAULT_FIELD_MANAGER}-webhook-tls")),
+            service_name: config
+                .webhook_service_name
+                .clone()
+                .unwrap_or_else(|| format!("{DEFAULT_FIELD_MANAGER}-webhook")),
+            webhook_configuration_name: config
+                .webhook_configuration_name
+                .clone()
+                .unwrap_or_else(|| DEFAULT_FIELD_MANAGER.to_string()),
+            validity: Duration::from_secs(365 * 24 * 3600),
+            renew_before: Duration::from_secs(30 * 24 * 3600),
+        })
+    }
+}
+
+/// PEM encoded serving certificate, its key, and the CA bundle to trust it
+#[derive(Debug, Clone)]
+pub struct ServingCertificate {
+    pub cert_pem: String,
+    pub key_pem: String,
+    pub ca_bundle: String,
+    pub not_after: DateTime<Utc>,
+}
+
+impl ServingCertificate {
+    fn from_secret(secret: &Secret) -> Option<Self> {
+        let data = secret.data.as_ref()?;
+        let get = |key: &str| String::from_utf8(data.get(key)?.0.clone()).ok();
+        let not_after = secret
+            .metadata
+            .annotations
+            .as_ref()?
+            .get(NOT_AFTER_ANNOTATION)?;
+        Some(Self {
+            cert_pem: get(TLS_CERT_KEY)?,
+            key_pem: get(TLS_KEY_KEY)?,
+            ca_bundle: get(CA_CERT_KEY)?,
+            not_after: DateTime::parse_from_rfc3339(not_after).ok()?.into(),
+        })
+    }
+
+    fn to_secret(&self, settings: &CertificateSettings) -> Secret {
+        let mut secret = Secret::default();
+        secret.metadata.name = Some(settings.secret_name.clone());
+        secret.metadata.namespace = Some(settings.namespace.clone());
+        secret.metadata.labels = Some(BTreeMap::from([(
+            OPERATOR_MANAGED_BY_LABEL.to_string(),
+            OPERATOR_MANAGED_BY.to_string(),
+        )]));
+        secret.metadata.annotations = Some(BTreeMap::from([(
+            NOT_AFTER_ANNOTATION.to_string(),
+            self.not_after.to_rfc3339(),
+        )]));
+        secret.type_ = Some("kubernetes.io/tls".to_string());
+        secret.data = Some(BTreeMap::from([
+            (
+                TLS_CERT_KEY.to_string(),
+                ByteString(self.cert_pem.clone().into()),
+            ),
+            (
+                TLS_KEY_KEY.to_string(),
+                ByteString(self.key_pem.clone().into()),
+            ),
+            (
+                CA_CERT_KEY.to_string(),
+                ByteString(self.ca_bundle.clone().into()),
+            ),
+        ]));
+        secret
+    }
+
+    /// The CA which signed the serving certificate, always first in the bundle
+    fn current_ca(&self) -> Option<String> {
+        let end = self.ca_bundle.find(PEM_CERTIFICATE_END)? + PEM_CERTIFICATE_END.len();
+        Some(format!("{}\n", &self.ca_bundle[..end]))
+    }
+}
+
+/// Generates, stores and rotates the webhook serving certificate, and injects its CA bundle into
+/// the webhook configurations and the CRD conversion webhook
+pub struct CertificateManager {
+    client: Client,
+    settings: CertificateSettings,
+}
+
+impl CertificateManager {
+    pub fn new(client: Client, settings: CertificateSettings) -> Self {
+        Self { client, settings }
+    }
+
+    /// Returns the serving certificate stored in the Secret, generating a new one when it is
+    /// missing or about to expire
+    pub async fn ensure(&self) -> eyre::Result<ServingCertificate> {
+        let secrets = Api::<Secret>::namespaced(self.client.clone(), &self.settings.namespace);
+        let present = secrets.get_opt(&self.settings.secret_name).await?;
+
+        let renew_at = Utc::now() + self.settings.renew_before;
+        let certificate = match present.as_ref().and_then(ServingCertificate::from_secret) {
+            Some(certificate) if certificate.not_after > renew_at => certificate,
+            _ => self.renew(&secrets, present).await?,
+        };
+
+        self.inject_ca_bundle(&certificate.ca_bundle).await?;
+        Ok(certificate)
+    }
+
+    /// Stores a new certificate, unless another replica stored one first: the Secret is only
+    /// created when missing, or replaced at the resourceVersion read, so every replica ends up
+    /// serving the certificate of the replica which won
+    async fn renew(
+        &self,
+        secrets: &Api<Secret>,
+        present: Option<Secret>,
+    ) -> eyre::Result<ServingCertificate> {
+        let name = &self.settings.secret_name;
+        info!(
+            "Webhook certificate: {} - {} generating...",
+            name, self.settings.namespace
+        );
+        // the previous CA stays trusted until the new certificate is served everywhere
+        let previous_ca = present
+            .as_ref()
+            .and_then(ServingCertificate::from_secret)
+            .and_then(|x| x.current_ca());
+        let certificate = generate_certificate(&self.settings, previous_ca)?;
+        let mut secret = certificate.to_secret(&self.settings);
+        let result = match present {
+            None => secrets.create(&PostParams::default(), &secret).await,
+            Some(present) => {
+                secret.metadata.resource_version = present.metadata.resource_version;
+                secrets.replace(name, &PostParams::default(), &secret).await
+            }
+        };
+
+        match result {
+            Ok(_) => {
+                info!(
+                    "Webhook certificate: {} - {} generated, valid until {}",
+                    name, self.settings.namespace, certificate.not_after
+                );
+                Ok(certificate)
+            }
+            Err(kube::Error::Api(e)) if e.code == 409 => {
+                info!(
+                    "Webhook certificate: {} - {} stored by another replica",
+                    name, self.settings.namespace
+                );
+                ServingCertificate::from_secret(&secrets.get(name).await?)
+                    .ok_or_else(|| eyre::eyre!("webhook certificate {name} is invalid"))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn inject_ca_bundle(&self, ca_bundle: &str) -> eyre::Result<()> {
+        let ca_bundle = ByteString(ca_bundle.as_bytes().to_vec());
+        let name = &self.settings.webhook_configuration_name;
+
+        let validating = Api::<ValidatingWebhookConfiguration>::all(self.client.clone());
+        if let Some(mut configuration) = validating.get_opt(name).await? {
+            let webhooks = configuration.webhooks.iter_mut().flatten();
+            let clients = webhooks.map(|x| &mut x.client_config.ca_bundle);
+            if set_ca_bundles(clients, &ca_bundle) {
+                validating
+                    .replace(name, &PostParams::default(), &configuration)
+                    .await?;
+                info!(
+                    "ValidatingWebhookConfiguration: {} CA bundle injected",
+                    name
+                );
+            }
+        }
+
+        let mutating = Api::<MutatingWebhookConfiguration>::all(self.client.clone());
+        if let Some(mut configuration) = mutating.get_opt(name).await? {
+            let webhooks = configuration.webhooks.iter_mut().flatten();
+            let clients = webhooks.map(|x| &mut x.client_config.ca_bundle);
+            if set_ca_bundles(clients, &ca_bundle) {
+                mutating
+                    .replace(name, &PostParams::default(), &configuration)
+                    .await?;
+                info!("MutatingWebhookConfiguration: {} CA bundle injected", name);
+            }
+        }
+
+        let crds = Api::<CustomResourceDefinition>::all(self.client.clone());
+        let crd_name = BitwardenSecret::crd_name();
+        let conversion_ca_bundle = crds.get_opt(crd_name).await?.map(|crd| {
+            crd.spec
+                .conversion
+                .and_then(|x| x.webhook)
+                .and_then(|x| x.client_config)
+                .map(|x| x.ca_bundle)
+        });
+        // only CRDs converted through a webhook have a client config to inject into
+        if let Some(Some(present)) = conversion_ca_bundle {
+            if present.as_ref() != Some(&ca_bundle) {
+                let patch = json!({
+                    "spec": {
+                        "conversion": {
+                            "webhook": {
+                                "clientConfig": {
+                                    "caBundle": ca_bundle,
+                                }
+                            }
+                        }
+                    }
+                });
+                crds.patch(crd_name, &PatchParams::default(), &Patch::Merge(&patch))
+                    .await?;
+                info!("CustomResourceDefinition: {} CA bundle injected", crd_name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sets every CA bundle, returns true if any of them changed
+fn set_ca_bundles<'a>(
+    ca_bundles: impl Iterator<Item = &'a mut Option<ByteString>>,
+    ca_bundle: &ByteString,
+) -> bool {
+    let mut changed = false;
+    for x in ca_bundles {
+        if x.as_ref() != Some(ca_bundle) {
+            *x = Some(ca_bundle.clone());
+            changed = true;
+        }
+    }
+    changed
+}
+
+fn to_ymd(date: DateTime<Utc>) -> (i32, u8, u8) {
+    (date.year(), date.month() as u8, date.day() as u8)
+}
+
+/// Generates a self-signed CA and a serving certificate for the webhook Service
+pub fn generate_certificate(
+    settings: &CertificateSettings,
+    previous_ca: Option<String>,
+) -> eyre::Result<ServingCertificate> {
+    let now = Utc::now();
+    // rcgen validity is day based, the stored expiry is truncated the same way
+    let not_before = to_ymd(now - Duration::from_secs(24 * 3600));
+    let not_after = (now + settings.validity)
+        .date_naive()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc();
+    let until = to_ymd(not_after);
+
+    let mut ca_params = CertificateParams::new(vec![]);
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    ca_params.distinguished_name = DistinguishedName::new();
+    ca_params
+        .distinguished_name
+        .push(DnType::CommonName, format!("{DEFAULT_FIELD_MANAGER}-ca"));
+    ca_params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    ca_params.not_before = rcgen::date_time_ymd(not_before.0, not_before.1, not_before.2);
+    ca_params.not_after = rcgen::date_time_ymd(until.0, until.1, until.2);
+    let ca = Certificate::from_params(ca_params)?;
+
+    let service = &settings.service_name;
+    let namespace = &settings.namespace;
+    let mut params = CertificateParams::new(vec![
+        service.clone(),
+        format!("{service}.{namespace}"),
+        format!("{service}.{namespace}.svc"),
+        format!("{service}.{namespace}.svc.cluster.local"),
+    ]);
+    params.distinguished_name = DistinguishedName::new();
+    params
+        .distinguished_name
+        .push(DnType::CommonName, format!("{service}.{namespace}.svc"));
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    params.not_before = rcgen::date_time_ymd(not_before.0, not_before.1, not_before.2);
+    params.not_after = rcgen::date_time_ymd(until.0, until.1, until.2);
+    let certificate = Certificate::from_params(params)?;
+
+    let mut ca_bundle = ca.serialize_pem()?;
+    if let Some(previous_ca) = previous_ca {
+        ca_bundle.push_str(&previous_ca);
+    }
+
+    Ok(ServingCertificate {
+        cert_pem: certificate.serialize_pem_with_signer(&ca)?,
+        key_pem: certificate.serialize_private_key_pem(),
+        ca_bundle,
+        not_after,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::operator::certificates::{
+        generate_certificate, CertificateSettings, ServingCertificate,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn rotated_certificate_keeps_previous_ca() {
+        let settings = CertificateSettings {
+            namespace: "operator".to_string(),
+            secret_name: "webhook-tls".to_string(),
+            service_name: "webhook".to_string(),
+            webhook_configuration_name: "webhook".to_string(),
+            validity: Duration::from_secs(365 * 24 * 3600),
+            renew_before: Duration::from_secs(30 * 24 * 3600),
+        };
+
+        let first = generate_certificate(&settings, None).unwrap();
+        assert_eq!(first.current_ca().unwrap(), first.ca_bundle);
+
+        let second = generate_certificate(&settings, first.current_ca()).unwrap();
+        assert!(second.ca_bundle.ends_with(&first.ca_bundle));
+        assert_ne!(second.current_ca(), first.current_ca());
+
+        let stored = ServingCertificate::from_secret(&second.to_secret(&settings)).unwrap();
+        assert_eq!(stored.ca_bundle, second.ca_bundle);
+        assert_eq!(stored.not_after, second.not_after);
+    }
+}
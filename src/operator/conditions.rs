@@ -0,0 +1,100 @@
+use chrono::Utc;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+
+/// The Secret is synced with Bitwarden and up to date
+pub(crate) const CONDITION_READY: &str = "Ready";
+/// Every Bitwarden item referenced by the BitwardenSecret could be fetched
+pub(crate) const CONDITION_SOURCE_AVAILABLE: &str = "SourceAvailable";
+/// The rendered Secret has been written to the cluster
+pub(crate) const CONDITION_SECRET_SYNCED: &str = "SecretSynced";
+/// The reconciliation is suspended by the paused annotation
+pub(crate) const CONDITION_PAUSED: &str = "Paused";
+
+pub(crate) const STATUS_TRUE: &str = "True";
+pub(crate) const STATUS_FALSE: &str = "False";
+
+/// Sets a condition, `lastTransitionTime` is only moved when the status of the condition changes
+pub fn set_condition(
+    conditions: &mut Vec<Condition>,
+    type_: &str,
+    status: &str,
+    reason: &str,
+    message: String,
+    observed_generation: Option<i64>,
+) {
+    let last_transition_time = conditions
+        .iter()
+        .find(|x| x.type_ == type_ && x.status == status)
+        .map(|x| x.last_transition_time.clone())
+        .unwrap_or_else(|| Time(Utc::now()));
+
+    conditions.retain(|x| x.type_ != type_);
+    conditions.push(Condition {
+        type_: type_.to_string(),
+        status: status.to_string(),
+        reason: reason.to_string(),
+        message,
+        observed_generation,
+        last_transition_time,
+    });
+    conditions.sort_by(|x, y| x.type_.cmp(&y.type_));
+}
+
+/// Returns true when the condition is present with a `True` status
+pub fn is_condition_true(conditions: &Option<Vec<Condition>>, type_: &str) -> bool {
+    conditions
+        .iter()
+        .flatten()
+        .any(|x| x.type_ == type_ && x.status == STATUS_TRUE)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::operator::conditions::{
+        is_condition_true, set_condition, CONDITION_READY, STATUS_FALSE, STATUS_TRUE,
+    };
+    use chrono::{TimeZone, Utc};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+
+    #[test]
+    fn last_transition_time_only_moves_on_status_change() {
+        let mut conditions = vec![];
+        set_condition(
+            &mut conditions,
+            CONDITION_READY,
+            STATUS_TRUE,
+            "Synced",
+            "".into(),
+            Some(1),
+        );
+        let past = Time(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap());
+        conditions[0].last_transition_time = past.clone();
+
+        set_condition(
+            &mut conditions,
+            CONDITION_READY,
+            STATUS_TRUE,
+            "Synced",
+            "".into(),
+            Some(2),
+        );
+        assert_eq!(conditions.len(), 1);
+        assert_eq!(conditions[0].last_transition_time, past);
+        assert_eq!(conditions[0].observed_generation, Some(2));
+        assert!(is_condition_true(
+            &Some(conditions.clone()),
+            CONDITION_READY
+        ));
+
+        set_condition(
+            &mut conditions,
+            CONDITION_READY,
+            STATUS_FALSE,
+            "Failed",
+            "".into(),
+            Some(2),
+        );
+        assert_ne!(conditions[0].last_transition_time, past);
+        assert!(!is_condition_true(&Some(conditions), CONDITION_READY));
+    }
+}
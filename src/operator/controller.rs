@@ -1,71 +1,439 @@
 use crate::bitwarden_cli::BitwardenCliClient;
-use crate::operator::generate_secret_from_bitwarden_secret;
-use crate::operator::schemas::{BitwardenSecret, BitwardenSecretError, BitwardenSecretStatus};
-use chrono::Utc;
-use futures::StreamExt;
+use crate::config::{Config, Reloadable};
+use crate::operator::conditions::{
+    is_condition_true, set_condition, CONDITION_PAUSED, CONDITION_READY, CONDITION_SECRET_SYNCED,
+    CONDITION_SOURCE_AVAILABLE, STATUS_FALSE, STATUS_TRUE,
+};
+use crate::operator::leader::{LeaderElectionSettings, LeaderElector};
+use crate::operator::push::PushedSecrets;
+use crate::operator::refresh::{parse_interval, RefreshSettings};
+use crate::operator::schemas::{
+    BitwardenSecret, BitwardenSecretError, DeletionPolicy, SecretTarget, FORCE_SYNC_ANNOTATION,
+    OPERATOR_FINALIZER, OPERATOR_HASH_ANNOTATION, OPERATOR_MANAGED_BY, OPERATOR_MANAGED_BY_LABEL,
+    OWNER_ANNOTATION, OWNER_UID_ANNOTATION, PAUSED_ANNOTATION,
+};
+use crate::operator::scope::{Scope, ScopeSettings};
+use crate::operator::shard::{Shard, ShardMembership, ShardingSettings};
+use crate::operator::{
+    apply_creation_policy, detect_drift, generate_secret_from_bitwarden_secret, is_owned_by,
+    owner_uid, secret_is_up_to_date, secret_owner, target_secret, RenderedSecret, SecretDrift,
+};
+use crate::operator::{push, rotation};
+use crate::shutdown::Shutdown;
+use chrono::{DateTime, Utc};
+use futures::channel::mpsc;
+use futures::future::join_all;
+use futures::stream::select_all;
+use futures::{StreamExt, TryStreamExt};
 use k8s_openapi::api::core::v1::Secret;
-use kube::api::{Patch, PatchParams, PostParams};
+use k8s_openapi::NamespaceResourceScope;
+use kube::api::{DeleteParams, ListParams, PartialObjectMeta, Patch, PatchParams, Preconditions};
 use kube::runtime::controller::Action;
-use kube::runtime::{watcher, Controller};
-use kube::{Api, Client, ResourceExt};
+use kube::runtime::events::{Event, EventType, Recorder, Reporter};
+use kube::runtime::finalizer::{self, finalizer};
+use kube::runtime::reflector::{self, ObjectRef, Store};
+use kube::runtime::{metadata_watcher, watcher, Controller, WatchStreamExt};
+use kube::{Api, Client, Resource, ResourceExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::{join, task};
+use tokio::{join, select, task};
 use tracing::{error, info, warn};
 
+pub(crate) const DEFAULT_FIELD_MANAGER: &str = "bitwarden-secret-operator";
+
+/// How server-side apply conflicts with other field managers are resolved
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// take ownership of conflicting fields
+    #[default]
+    Force,
+    /// fail the reconcile and leave conflicting fields to their current owner
+    Fail,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "force" => Ok(ConflictPolicy::Force),
+            "fail" => Ok(ConflictPolicy::Fail),
+            x => Err(eyre::eyre!(
+                "invalid conflict policy: {x}, expected `Force` or `Fail`"
+            )),
+        }
+    }
+}
+
+/// Server-side apply settings used for every write done by the operator
+#[derive(Debug, Clone)]
+pub struct ApplySettings {
+    pub field_manager: String,
+    pub conflict_policy: ConflictPolicy,
+}
+
+impl Default for ApplySettings {
+    fn default() -> Self {
+        Self {
+            field_manager: DEFAULT_FIELD_MANAGER.to_string(),
+            conflict_policy: ConflictPolicy::default(),
+        }
+    }
+}
+
+impl ApplySettings {
+    pub fn from_config(config: &Config) -> eyre::Result<Self> {
+        let mut settings = ApplySettings::default();
+        if let Some(field_manager) = &config.field_manager {
+            settings.field_manager = field_manager.clone();
+        }
+        if let Some(conflict_policy) = &config.apply_conflict_policy {
+            settings.conflict_policy = conflict_policy.parse()?;
+        }
+        Ok(settings)
+    }
+
+    pub(crate) fn patch_params(&self) -> PatchParams {
+        let params = PatchParams::apply(&self.field_manager);
+        match self.conflict_policy {
+            ConflictPolicy::Force => params.force(),
+            ConflictPolicy::Fail => params,
+        }
+    }
+}
+
+/// Settings of the BitwardenSecret controller
+#[derive(Debug, Clone)]
+pub struct OperatorSettings {
+    pub apply: ApplySettings,
+    pub refresh: RefreshSettings,
+    /// how often the vault of the CLI is synced
+    pub sync_interval: Duration,
+    /// identity of the replica reported in the events
+    pub instance: Option<String>,
+    /// watched namespaces and resources
+    pub scope: ScopeSettings,
+    /// only the leader runs the controllers when set
+    pub leader_election: Option<LeaderElectionSettings>,
+    /// every replica reconciles its own shard of the resources when set
+    pub sharding: Option<ShardingSettings>,
+}
+
+impl OperatorSettings {
+    pub fn from_config(config: &Config) -> eyre::Result<Self> {
+        let settings = Self {
+            apply: ApplySettings::from_config(config)?,
+            refresh: RefreshSettings::from_config(config)?,
+            sync_interval: match &config.bw_sync_interval {
+                Some(x) => parse_interval(x)?,
+                None => Duration::from_secs(60),
+            },
+            instance: config.pod_name.clone(),
+            scope: ScopeSettings::from_config(config)?,
+            leader_election: LeaderElectionSettings::from_config(config)?,
+            sharding: ShardingSettings::from_config(config)?,
+        };
+        if settings.sync_interval.is_zero() {
+            eyre::bail!("BW_SYNC_INTERVAL can't be zero");
+        }
+        if settings.leader_election.is_some() && settings.sharding.is_some() {
+            eyre::bail!("leader election and sharding can't be enabled together");
+        }
+        Ok(settings)
+    }
+}
+
 pub struct BitwardenOperator {
     cli: Arc<BitwardenCliClient>,
     client: Client,
+    settings: OperatorSettings,
+    apply: Reloadable<ApplySettings>,
+    refresh: Reloadable<RefreshSettings>,
 }
 
 #[derive(Clone)]
-struct KubeContext {
+pub(crate) struct KubeContext {
     /// kubernetes client
-    client: Client,
-    bitwarden_cli: Arc<BitwardenCliClient>,
+    pub(crate) client: Client,
+    pub(crate) bitwarden_cli: Arc<BitwardenCliClient>,
+    /// server-side apply settings, reloaded on SIGHUP
+    pub(crate) apply: Reloadable<ApplySettings>,
+    /// refresh defaults, reloaded on SIGHUP
+    pub(crate) refresh: Reloadable<RefreshSettings>,
+    /// reporter of the published events
+    pub(crate) reporter: Reporter,
+    /// watched namespaces and resources
+    pub(crate) scope: Scope,
+    /// metadata of the Secrets written by the operator
+    pub(crate) secrets: ManagedSecrets,
+    /// BitwardenPushSecrets by the Secret they push
+    pub(crate) pushed_secrets: PushedSecrets,
+    /// shard of this replica, every resource is owned when sharding is disabled
+    pub(crate) shard: Option<Shard>,
+    /// stops the controllers
+    pub(crate) shutdown: Shutdown,
+}
+
+impl KubeContext {
+    /// Returns true when this replica reconciles the resource, i.e. its namespace is watched and
+    /// it falls in the shard of the replica
+    pub(crate) fn owns<K: Resource>(&self, obj: &K) -> bool {
+        self.scope.allows(&obj.namespace().unwrap_or_default())
+            && self.shard.as_ref().is_none_or(|x| x.owns(obj))
+    }
+}
+
+/// Metadata of the Secrets labelled as managed by the operator, cached by the owned Secret watches
+/// of the controllers so memory scales with the managed Secrets rather than with the cluster
+#[derive(Clone, Default)]
+pub(crate) struct ManagedSecrets {
+    stores: Vec<Store<PartialObjectMeta<Secret>>>,
+}
+
+impl ManagedSecrets {
+    /// Looks the Secret up in the cache, or in the cluster when it isn't managed by the operator
+    /// yet, e.g. before its first write or when it was created by someone else
+    pub(crate) async fn get(
+        &self,
+        client: &Client,
+        target: &SecretTarget,
+    ) -> kube::Result<Option<PartialObjectMeta<Secret>>> {
+        let key = ObjectRef::new(&target.name).within(&target.namespace);
+        for store in &self.stores {
+            // the watches are started with the controllers, wait for their first listing
+            let _ = store.wait_until_ready().await;
+            if let Some(secret) = store.get(&key) {
+                return Ok(Some((*secret).clone()));
+            }
+        }
+        Api::<Secret>::namespaced(client.clone(), &target.namespace)
+            .get_metadata_opt(&target.name)
+            .await
+    }
+}
+
+/// Label selector of the Secrets written by the operator
+fn managed_by_selector() -> String {
+    format!("{}={}", OPERATOR_MANAGED_BY_LABEL, OPERATOR_MANAGED_BY)
 }
 
 impl BitwardenOperator {
-    pub fn new(cli: Arc<BitwardenCliClient>, client: Client) -> Self {
-        Self { cli, client }
+    pub fn new(cli: Arc<BitwardenCliClient>, client: Client, settings: OperatorSettings) -> Self {
+        Self {
+            cli,
+            client,
+            apply: Reloadable::new(settings.apply.clone()),
+            refresh: Reloadable::new(settings.refresh.clone()),
+            settings,
+        }
     }
 
-    pub async fn start(&self) -> eyre::Result<()> {
+    /// Applies the settings which can change at runtime: the refresh defaults and the apply
+    /// conflict policy. The field manager owns the fields already written, it is kept.
+    pub fn reload(&self, settings: &OperatorSettings) {
+        let mut apply = settings.apply.clone();
+        if apply.field_manager != self.settings.apply.field_manager {
+            warn!(
+                "the field manager can't change at runtime, keeping: {}",
+                self.settings.apply.field_manager
+            );
+            apply.field_manager = self.settings.apply.field_manager.clone();
+        }
+        self.apply.set(apply);
+        self.refresh.set(settings.refresh.clone());
+        info!("Configuration reloaded");
+    }
+
+    /// Runs the controllers until the shutdown is requested and the in-flight reconciles are done,
+    /// the Lease held by the replica is then released so another one takes over right away
+    pub async fn start(&self, shutdown: Shutdown) -> eyre::Result<()> {
         info!("Starting Operator...");
+        let mut member_lease = None;
+        let shard = match self.settings.sharding.clone() {
+            Some(settings) => {
+                let membership = ShardMembership::new(self.client.clone(), settings);
+                select! {
+                    _ = membership.join() => {}
+                    _ = shutdown.clone().requested() => return Ok(()),
+                }
+                let shard = membership.shard();
+                let lease = membership.lease();
+                member_lease = Some((lease, task::spawn(membership.run())));
+                Some(shard)
+            }
+            None => None,
+        };
+        // one metadata-only watch of the managed Secrets per watched namespace
+        let scope = Scope::start(self.client.clone(), self.settings.scope.clone()).await;
+        let mut secrets = ManagedSecrets::default();
+        let mut secret_streams = vec![];
+        for api in scope.settings().apis::<Secret>(&self.client) {
+            let (store, writer) = reflector::store();
+            let config = watcher::Config::default().labels(&managed_by_selector());
+            secret_streams.push(
+                metadata_watcher(api, config)
+                    .default_backoff()
+                    .reflect(writer)
+                    .touched_objects()
+                    .boxed(),
+            );
+            secrets.stores.push(store);
+        }
+
         let context = Arc::new(KubeContext {
             client: self.client.clone(),
             bitwarden_cli: self.cli.clone(),
+            apply: self.apply.clone(),
+            refresh: self.refresh.clone(),
+            reporter: Reporter {
+                controller: DEFAULT_FIELD_MANAGER.to_string(),
+                instance: self.settings.instance.clone(),
+            },
+            scope,
+            secrets,
+            pushed_secrets: PushedSecrets::default(),
+            shard,
+            shutdown: shutdown.clone(),
         });
 
         let cli = self.cli.clone();
+        let sync_interval = self.settings.sync_interval;
 
-        // background task to sync the CLI secrets every X seconds
+        // background task to sync the CLI secrets every X seconds, standby replicas keep their
+        // vault synced as well to take over right away
         task::spawn(async move {
             let cli = cli.clone();
             loop {
-                tokio::time::sleep(Duration::from_secs(60)).await;
+                tokio::time::sleep(sync_interval).await;
                 let _ = cli.sync().await;
             }
         });
 
-        // generate secret
-        let bitwarden_secrets = Api::<BitwardenSecret>::all(self.client.clone());
-        let secrets = Api::<Secret>::all(self.client.clone());
-
-        Controller::new(bitwarden_secrets.clone(), watcher::Config::default())
-            .owns(secrets, watcher::Config::default())
-            .run(reconcile_bitwarden_secret, error_policy, context)
-            .for_each(|res| async move {
-                match res {
-                    Ok(o) => info!("reconciled {}:{}", o.0.namespace.unwrap(), o.0.name),
-                    Err(e) => warn!("reconcile failed: {}", e),
+        let elector = self
+            .settings
+            .leader_election
+            .clone()
+            .map(|x| LeaderElector::new(self.client.clone(), x));
+        if let Some(elector) = &elector {
+            select! {
+                _ = elector.acquire() => {}
+                _ = shutdown.clone().requested() => return Ok(()),
+            }
+        }
+
+        if let Err(e) = sweep_orphaned_secrets(&context).await {
+            warn!("sweeping orphaned secrets failed: {}", e);
+        }
+
+        // the managed Secrets are routed to the controller of their owner's namespace, which
+        // differs from theirs when the Secret is written to another namespace
+        let scope = context.scope.settings().clone();
+        let (routes, owned_secrets): (Vec<_>, Vec<_>) = scope
+            .apis::<BitwardenSecret>(&self.client)
+            .iter()
+            .map(|_| mpsc::unbounded())
+            .unzip();
+        let routing = task::spawn(select_all(secret_streams).for_each(move |res| {
+            match res {
+                Ok(secret) => {
+                    let route = secret_owner(&secret)
+                        .and_then(|owner| scope.api_index(owner.namespace.as_deref()?));
+                    if let Some(route) = route.and_then(|x| routes.get(x)) {
+                        let _ = route.unbounded_send(Ok(secret));
+                    }
                 }
-            })
-            .await;
+                Err(e) => warn!("watching managed secrets failed: {}", e),
+            }
+            futures::future::ready(())
+        }));
+
+        // generate secret, one controller per watched namespace
+        let scope = context.scope.settings();
+        let bitwarden_secrets_controllers = scope
+            .apis::<BitwardenSecret>(&self.client)
+            .into_iter()
+            .zip(owned_secrets)
+            .map(|(bitwarden_secrets, owned_secrets)| {
+                let (reader, writer) = reflector::store();
+                let ctx = context.clone();
+                let bitwarden_secrets = watcher(bitwarden_secrets, scope.watcher_config())
+                    .default_backoff()
+                    .reflect(writer)
+                    .inspect_ok(move |event| {
+                        // objects which no longer match the label selector are deleted from the
+                        // watch, they are never reconciled again
+                        if let watcher::Event::Deleted(obj) = event {
+                            task::spawn(release_out_of_scope(Arc::new(obj.clone()), ctx.clone()));
+                        }
+                    })
+                    .touched_objects();
+                let controller = Controller::for_stream(bitwarden_secrets, reader)
+                    .watches_stream(owned_secrets, |secret| secret_owner(&secret));
+                with_shared_triggers(controller, &context)
+                    .run(reconcile_bitwarden_secret, error_policy, context.clone())
+                    .for_each(|res| async move {
+                        match res {
+                            Ok(o) => info!("reconciled {}:{}", o.0.namespace.unwrap(), o.0.name),
+                            Err(e) => warn!("reconcile failed: {}", e),
+                        }
+                    })
+            });
+
+        let controllers = async {
+            join!(
+                join_all(bitwarden_secrets_controllers),
+                push::run(context.clone())
+            );
+            routing.abort();
+        };
+        match elector {
+            Some(elector) => {
+                // the controllers stop with the process, the next leader resumes from a fresh state
+                select! {
+                    _ = controllers => {
+                        elector.release().await;
+                        Ok(())
+                    }
+                    _ = elector.hold() => Err(eyre::eyre!("leadership lost")),
+                }
+            }
+            None => {
+                controllers.await;
+                if let Some((lease, membership)) = member_lease {
+                    membership.abort();
+                    if let Err(e) = lease.release().await {
+                        warn!("member Lease couldn't be released: {}", e);
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
 
-        Ok(())
+/// Reconciles every resource when the shard rebalances or a namespace enters the scope, the
+/// resources gained are picked up by the next reconcile, and stops starting new reconciles once
+/// the shutdown is requested
+pub(crate) fn with_shared_triggers<K>(
+    mut controller: Controller<K>,
+    ctx: &KubeContext,
+) -> Controller<K>
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug + Send + Sync + 'static,
+{
+    if let Some(shard) = &ctx.shard {
+        controller = controller.reconcile_all_on(shard.rebalanced());
     }
+    if let Some(namespaces_changed) = ctx.scope.namespaces_changed() {
+        controller = controller.reconcile_all_on(namespaces_changed);
+    }
+    controller.graceful_shutdown_on(ctx.shutdown.clone().requested())
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -74,6 +442,8 @@ pub enum BitwardenOperatorError {
     BitwardenSecretError(#[from] BitwardenSecretError),
     #[error("KubernetesClientError: {0} ({0:?})")]
     KubernetesError(#[from] kube::error::Error),
+    #[error("FinalizerError: {0} ({0:?})")]
+    FinalizerError(#[source] Box<finalizer::Error<BitwardenOperatorError>>),
 }
 
 pub type BitwardenOperatorResult<T, E = BitwardenOperatorError> = Result<T, E>;
@@ -82,112 +452,683 @@ async fn reconcile_bitwarden_secret(
     obj: Arc<BitwardenSecret>,
     ctx: Arc<KubeContext>,
 ) -> BitwardenOperatorResult<Action> {
-    let manifest_name = &obj.name_any();
-    info!("reconcile request: {}", manifest_name);
+    let namespace = obj.namespace().unwrap_or_default();
+    if !ctx.scope.allows(&namespace) {
+        // the resources of namespaces never in scope may belong to another operator
+        if ctx.scope.has_left(&namespace) {
+            release_out_of_scope(obj, ctx).await;
+        }
+        return Ok(Action::await_change());
+    }
+    if !ctx.owns(&*obj) {
+        return Ok(Action::await_change());
+    }
+    info!("reconcile request: {}", obj.name_any());
     metrics::counter!("reconcile_requests_total").increment(1);
 
-    // avoid refreshing if unnecessary
-    if let Some(status) = &obj.status {
-        // TODO configuration later
-        let now = Utc::now();
-        if status
-            .last_updated
-            .is_some_and(|x| now < x + Duration::from_secs(3600))
+    let api = Api::<BitwardenSecret>::namespaced(ctx.client.clone(), &obj.namespace().unwrap());
+    finalizer(&api, OPERATOR_FINALIZER, obj, |event| async {
+        match event {
+            finalizer::Event::Apply(obj) => apply_bitwarden_secret(obj, ctx.clone()).await,
+            finalizer::Event::Cleanup(obj) => cleanup_bitwarden_secret(obj, ctx.clone()).await,
+        }
+    })
+    .await
+    .map_err(|e| BitwardenOperatorError::FinalizerError(Box::new(e)))
+}
+
+/// Removes the finalizer of a BitwardenSecret which left the scope, so it can be deleted without
+/// the operator. Its Secret is left as is.
+async fn release_out_of_scope(obj: Arc<BitwardenSecret>, ctx: Arc<KubeContext>) {
+    let Some(finalizers) = finalizers_without_operator(&obj) else {
+        return;
+    };
+    if ctx.shard.as_ref().is_some_and(|x| !x.owns(&*obj)) {
+        return;
+    }
+
+    // the resourceVersion fails the patch if the finalizers changed since
+    let api = Api::<BitwardenSecret>::namespaced(ctx.client.clone(), &obj.namespace().unwrap());
+    let patch = json!({
+        "metadata": {
+            "resourceVersion": obj.resource_version(),
+            "finalizers": finalizers,
+        }
+    });
+    match api
+        .patch(
+            &obj.name_any(),
+            &PatchParams::default(),
+            &Patch::Merge(&patch),
+        )
+        .await
+    {
+        Ok(_) => info!(
+            "BitwardenSecret: {} left the scope, released",
+            obj.name_any()
+        ),
+        Err(e) => warn!(
+            "BitwardenSecret: {} couldn't be released: {}",
+            obj.name_any(),
+            e
+        ),
+    }
+}
+
+/// Finalizers of the BitwardenSecret without the operator's, `None` when it doesn't hold it
+fn finalizers_without_operator(obj: &BitwardenSecret) -> Option<Vec<String>> {
+    let finalizers = obj.finalizers();
+    finalizers.iter().any(|x| x == OPERATOR_FINALIZER).then(|| {
+        finalizers
+            .iter()
+            .filter(|x| *x != OPERATOR_FINALIZER)
+            .cloned()
+            .collect()
+    })
+}
+
+async fn apply_bitwarden_secret(
+    obj: Arc<BitwardenSecret>,
+    ctx: Arc<KubeContext>,
+) -> BitwardenOperatorResult<Action> {
+    let manifest_name = &obj.name_any();
+
+    if is_paused(&*obj) {
+        return pause_bitwarden_secret(&obj, &ctx).await;
+    }
+
+    let target = target_secret(&obj);
+    let force_sync = pending_force_sync(&obj);
+
+    // the Secret written by the last successful sync is compared with its checksum. The cache
+    // only holds its metadata, its content is read from the cluster only when it changed since
+    let drift = match &obj.status {
+        Some(status)
+            if status.target.as_ref() == Some(&target)
+                && is_condition_true(&status.conditions, CONDITION_READY) =>
         {
-            // TODO configuration later
-            return Ok(Action::requeue(Duration::from_secs(60)));
+            match ctx.secrets.get(&ctx.client, &target).await? {
+                None => detect_drift(&obj, None),
+                Some(x)
+                    if x.metadata.resource_version.is_some()
+                        && x.metadata.resource_version == status.synced_resource_version =>
+                {
+                    None
+                }
+                Some(_) => {
+                    let live = Api::<Secret>::namespaced(ctx.client.clone(), &target.namespace)
+                        .get_opt(&target.name)
+                        .await?;
+                    detect_drift(&obj, live.as_ref())
+                }
+            }
         }
+        _ => None,
     };
+    if let Some(drift) = drift {
+        let note = format!(
+            "Secret {}/{} was {} outside of the operator, repairing it",
+            target.namespace,
+            target.name,
+            drift.verb()
+        );
+        warn!("{}", note);
+        metrics::counter!("secret_drifts_total", "drift" => drift.verb()).increment(1);
+        publish_event(&ctx, &*obj, EventType::Warning, drift.reason(), note).await;
+    }
 
-    let target_namespace = &obj
-        .spec
-        .namespace
-        .clone()
-        .unwrap_or_else(|| obj.namespace().unwrap());
-    let secret_name = obj.spec.name.clone().unwrap_or_else(|| obj.name_any());
+    // avoid refreshing if nothing changed and the next refresh isn't due yet, a resumed resource
+    // is synced right away
+    if let (Some(status), None, None) = (&obj.status, &force_sync, drift) {
+        if status.target.as_ref() == Some(&target)
+            && !is_condition_true(&status.conditions, CONDITION_PAUSED)
+            && status.observed_generation == obj.metadata.generation
+            && is_condition_true(&status.conditions, CONDITION_READY)
+        {
+            match status.next_refresh_time {
+                None => return Ok(Action::await_change()),
+                Some(next_refresh_time) if Utc::now() < next_refresh_time => {
+                    return Ok(requeue_at(next_refresh_time))
+                }
+                Some(_) => {}
+            }
+        }
+    };
 
-    let namespace = Api::<Secret>::namespaced(ctx.client.clone(), target_namespace);
-    let (present_secret_result, expected_secret_result) = join!(
-        namespace.get_opt(&secret_name),
-        generate_secret_from_bitwarden_secret(ctx.bitwarden_cli.clone(), obj.clone())
-    );
+    let result = match &force_sync {
+        // the items are read from the local copy of the vault, it is pulled first
+        Some(value) => match ctx.bitwarden_cli.sync().await {
+            Ok(()) => {
+                info!("BitwardenSecret: {} force-sync: {}", manifest_name, value);
+                sync_secret(&obj, &ctx, &target, drift).await
+            }
+            Err(e) => Err(BitwardenSecretError::VaultSyncFailed(e.to_string()).into()),
+        },
+        None => sync_secret(&obj, &ctx, &target, drift).await,
+    };
 
-    let secret = match expected_secret_result {
-        Ok(secret) => secret,
+    let generation = obj.metadata.generation;
+    let mut status = obj.status.clone().unwrap_or_default();
+    status.observed_generation = generation;
+    status.conflict = None;
+    let conditions = status.conditions.get_or_insert_with(Vec::new);
+    conditions.retain(|x| x.type_ != CONDITION_PAUSED);
+    let action = match result {
+        Ok(synced) => {
+            let message = format!("Secret {}/{} is synced", target.namespace, target.name);
+            set_condition(
+                conditions,
+                CONDITION_READY,
+                STATUS_TRUE,
+                "Synced",
+                message,
+                generation,
+            );
+            set_condition(
+                conditions,
+                CONDITION_SOURCE_AVAILABLE,
+                STATUS_TRUE,
+                "ItemsFetched",
+                "Every Bitwarden item could be fetched".to_string(),
+                generation,
+            );
+            set_condition(
+                conditions,
+                CONDITION_SECRET_SYNCED,
+                STATUS_TRUE,
+                synced.write.reason(),
+                format!("Keys: {}", synced.keys.join(", ")),
+                generation,
+            );
+            let note = format!(
+                "Secret {}/{} {} with keys: {}",
+                target.namespace,
+                target.name,
+                synced.write.verb(),
+                synced.keys.join(", ")
+            );
+            publish_event(&ctx, &*obj, EventType::Normal, synced.write.reason(), note).await;
+            if !synced.rotated.is_empty() {
+                let note = format!("Rotated keys: {}", synced.rotated.join(", "));
+                publish_event(&ctx, &*obj, EventType::Normal, "Rotated", note).await;
+            }
+            status.checksum = synced.checksum;
+            status.last_updated = Some(Utc::now());
+            status.target = Some(target);
+            status.synced_keys = Some(synced.keys);
+            status.synced_labels = Some(synced.labels);
+            status.synced_resource_version = synced.resource_version;
+            status.next_refresh_time = synced.next_refresh_time;
+            status.last_rotated = Some(synced.last_rotated).filter(|x| !x.is_empty());
+            if force_sync.is_some() {
+                status.force_sync = force_sync;
+            }
+            Ok(synced
+                .next_refresh_time
+                .map_or_else(Action::await_change, requeue_at))
+        }
         Err(e) => {
-            // Log the error and return early with Err
             error!(
                 "Failed to reconcile BitwardenSecret: {}, {}",
                 manifest_name,
                 e.to_string()
             );
-            return Err(BitwardenOperatorError::BitwardenSecretError(e));
+            let (reason, source_available, secret_synced) = match &e {
+                BitwardenOperatorError::BitwardenSecretError(
+                    x @ BitwardenSecretError::SecretConflict(..),
+                ) => {
+                    status.conflict = Some(x.to_string());
+                    (x.reason(), Some(STATUS_TRUE), STATUS_FALSE)
+                }
+                BitwardenOperatorError::BitwardenSecretError(x) if x.is_source_error() => {
+                    (x.reason(), Some(STATUS_FALSE), STATUS_FALSE)
+                }
+                BitwardenOperatorError::BitwardenSecretError(x) => (x.reason(), None, STATUS_FALSE),
+                _ => ("ApplyFailed", Some(STATUS_TRUE), STATUS_FALSE),
+            };
+            let message = e.to_string();
+            publish_event(&ctx, &*obj, EventType::Warning, reason, message.clone()).await;
+            set_condition(
+                conditions,
+                CONDITION_READY,
+                STATUS_FALSE,
+                reason,
+                message.clone(),
+                generation,
+            );
+            if let Some(source_available) = source_available {
+                set_condition(
+                    conditions,
+                    CONDITION_SOURCE_AVAILABLE,
+                    source_available,
+                    if source_available == STATUS_TRUE {
+                        "ItemsFetched"
+                    } else {
+                        reason
+                    },
+                    message.clone(),
+                    generation,
+                );
+            }
+            set_condition(
+                conditions,
+                CONDITION_SECRET_SYNCED,
+                secret_synced,
+                reason,
+                message,
+                generation,
+            );
+            Err(e)
         }
     };
+    patch_status(&ctx, &*obj, status).await?;
 
-    if present_secret_result?.is_some() {
+    if action.is_ok() {
+        metrics::counter!("reconcile_requests_success_total").increment(1);
+    }
+    action
+}
+
+/// Returns true when the paused annotation suspends the reconciliation of the resource
+fn is_paused(obj: &impl Resource) -> bool {
+    obj.annotations()
+        .get(PAUSED_ANNOTATION)
+        .is_some_and(|x| x == "true")
+}
+
+/// Value of the force-sync annotation when it hasn't been handled yet
+fn pending_force_sync(obj: &BitwardenSecret) -> Option<String> {
+    let value = obj.annotations().get(FORCE_SYNC_ANNOTATION)?;
+    let handled = obj.status.as_ref().and_then(|x| x.force_sync.as_ref());
+    (handled != Some(value)).then(|| value.clone())
+}
+
+/// Records the pause in the status, the resource is reconciled again once the annotation is
+/// removed
+async fn pause_bitwarden_secret(
+    obj: &BitwardenSecret,
+    ctx: &KubeContext,
+) -> BitwardenOperatorResult<Action> {
+    let mut status = obj.status.clone().unwrap_or_default();
+    if !is_condition_true(&status.conditions, CONDITION_PAUSED) {
+        info!("BitwardenSecret: {} paused", obj.name_any());
+        set_condition(
+            status.conditions.get_or_insert_with(Vec::new),
+            CONDITION_PAUSED,
+            STATUS_TRUE,
+            "Paused",
+            format!("Reconciliation is suspended by the {PAUSED_ANNOTATION} annotation"),
+            obj.metadata.generation,
+        );
+        patch_status(ctx, obj, status).await?;
+        publish_event(
+            ctx,
+            obj,
+            EventType::Normal,
+            "Paused",
+            "Reconciliation paused".to_string(),
+        )
+        .await;
+    }
+    Ok(Action::await_change())
+}
+
+/// Outcome of a successful sync of a BitwardenSecret
+struct SyncedSecret {
+    checksum: String,
+    keys: Vec<String>,
+    labels: Vec<String>,
+    resource_version: Option<String>,
+    write: SecretWrite,
+    next_refresh_time: Option<DateTime<Utc>>,
+    /// keys rotated in Bitwarden during the sync
+    rotated: Vec<String>,
+    last_rotated: BTreeMap<String, DateTime<Utc>>,
+}
+
+/// What happened to the target Secret, or the pushed Bitwarden item, during a sync
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SecretWrite {
+    Created,
+    Updated,
+    Unchanged,
+}
+
+impl SecretWrite {
+    pub(crate) fn reason(&self) -> &'static str {
+        match self {
+            SecretWrite::Created => "Created",
+            SecretWrite::Updated => "Updated",
+            SecretWrite::Unchanged => "Unchanged",
+        }
+    }
+
+    pub(crate) fn verb(&self) -> &'static str {
+        match self {
+            SecretWrite::Created => "created",
+            SecretWrite::Updated => "updated",
+            SecretWrite::Unchanged => "unchanged",
+        }
+    }
+}
+
+/// Renders the Secret from Bitwarden and writes it to the cluster if it changed, or if it drifted
+/// since the cache may not show the drift yet
+async fn sync_secret(
+    obj: &Arc<BitwardenSecret>,
+    ctx: &KubeContext,
+    target: &SecretTarget,
+    drift: Option<SecretDrift>,
+) -> BitwardenOperatorResult<SyncedSecret> {
+    if !ctx.scope.allows(&target.namespace) {
+        return Err(BitwardenSecretError::NamespaceOutOfScope(target.namespace.clone()).into());
+    }
+    let now = Utc::now();
+    let next_refresh_time = ctx.refresh.get().next_refresh(obj, now)?;
+
+    let namespace = Api::<Secret>::namespaced(ctx.client.clone(), &target.namespace);
+    let (present_secret_result, expected_secret_result) = join!(
+        ctx.secrets.get(&ctx.client, target),
+        generate_secret_from_bitwarden_secret(ctx.bitwarden_cli.clone(), obj.clone(), now)
+    );
+
+    let RenderedSecret {
+        mut secret,
+        rotated,
+    } = expected_secret_result?;
+    let present_secret = present_secret_result?;
+    apply_creation_policy(obj, &mut secret, present_secret.as_ref())?;
+
+    let checksum = secret
+        .annotations()
+        .get(OPERATOR_HASH_ANNOTATION)
+        .cloned()
+        .unwrap_or_default();
+    let keys = secret.data.iter().flatten().map(|x| x.0.clone()).collect();
+    let labels = secret.labels().keys().cloned().collect();
+
+    let write = match (&present_secret, drift) {
+        (None, _) | (_, Some(SecretDrift::Deleted)) => SecretWrite::Created,
+        (_, Some(SecretDrift::Modified)) => SecretWrite::Updated,
+        (Some(present), None) if secret_is_up_to_date(&secret, &present.metadata) => {
+            SecretWrite::Unchanged
+        }
+        (Some(_), None) => SecretWrite::Updated,
+    };
+    let resource_version = if write != SecretWrite::Unchanged {
         info!(
-            "Secret: {} - {} replacing...",
+            "Secret: {} - {} applying...",
             secret.name_any(),
             secret.namespace().unwrap()
         );
-        namespace
-            .replace(
+        let applied = namespace
+            .patch(
                 &secret.name_any(),
-                &PostParams {
-                    dry_run: false,
-                    field_manager: Default::default(),
-                },
-                &secret,
+                &ctx.apply.get().patch_params(),
+                &Patch::Apply(&secret),
             )
             .await?;
         info!(
-            "Secret: {} - {} replaced!",
+            "Secret: {} - {} applied!",
             secret.name_any(),
             secret.namespace().unwrap()
         );
+        applied.metadata.resource_version
     } else {
         info!(
-            "Secret: {} - {} creating...",
+            "Secret: {} - {} unchanged, skipping write",
             secret.name_any(),
             secret.namespace().unwrap()
         );
-        namespace
-            .create(
-                &PostParams {
-                    dry_run: false,
-                    field_manager: Default::default(),
-                },
-                &secret,
-            )
-            .await?;
+        present_secret.and_then(|x| x.metadata.resource_version)
+    };
+
+    // the Secret moved, the previous one must not keep live credentials around
+    if let Some(previous) = obj.status.as_ref().and_then(|x| x.target.as_ref()) {
+        if previous != target {
+            release_secret(&ctx.client, obj, previous).await?;
+        }
+    }
+
+    let mut last_rotated = obj
+        .status
+        .as_ref()
+        .and_then(|x| x.last_rotated.clone())
+        .unwrap_or_default();
+    rotation::record_rotations(obj, &mut last_rotated, &rotated, now);
+    let next_refresh_time = match (
+        next_refresh_time,
+        rotation::next_rotation(obj, &last_rotated)?,
+    ) {
+        (Some(x), Some(y)) => Some(x.min(y)),
+        (x, y) => x.or(y),
+    };
+
+    Ok(SyncedSecret {
+        checksum,
+        keys,
+        labels,
+        resource_version,
+        write,
+        next_refresh_time,
+        rotated,
+        last_rotated,
+    })
+}
+
+pub(crate) fn requeue_at(time: DateTime<Utc>) -> Action {
+    let delay = (time - Utc::now()).to_std().unwrap_or_default();
+    Action::requeue(delay)
+}
+
+async fn cleanup_bitwarden_secret(
+    obj: Arc<BitwardenSecret>,
+    ctx: Arc<KubeContext>,
+) -> BitwardenOperatorResult<Action> {
+    let target = target_secret(&obj);
+    release_secret(&ctx.client, &obj, &target).await?;
+    if let Some(previous) = obj.status.as_ref().and_then(|x| x.target.as_ref()) {
+        if previous != &target {
+            release_secret(&ctx.client, &obj, previous).await?;
+        }
+    }
+    Ok(Action::await_change())
+}
+
+/// Deletes or releases a Secret previously written for the BitwardenSecret, according to its
+/// deletion policy
+async fn release_secret(
+    client: &Client,
+    obj: &BitwardenSecret,
+    target: &SecretTarget,
+) -> BitwardenOperatorResult<()> {
+    let namespace = Api::<Secret>::namespaced(client.clone(), &target.namespace);
+
+    // secrets not owned by the BitwardenSecret (merged, orphaned or adopted by someone else) are
+    // left untouched
+    let Some(secret) = namespace.get_metadata_opt(&target.name).await? else {
+        return Ok(());
+    };
+    if !is_owned_by(&secret, obj) {
         info!(
-            "Secret: {} - {} created!",
-            secret.name_any(),
-            secret.namespace().unwrap()
+            "Secret: {} - {} not owned by BitwardenSecret: {}, keeping it",
+            target.name,
+            target.namespace,
+            obj.name_any()
         );
+        return Ok(());
     }
 
-    let status = json!({
-        "status": BitwardenSecretStatus {
-            checksum: "todo".to_string(),
-            last_updated: Some(Utc::now()),
+    match obj.spec.deletion_policy.unwrap_or_default() {
+        DeletionPolicy::Delete => delete_secret(&namespace, &secret).await,
+        DeletionPolicy::Retain => {
+            info!(
+                "Secret: {} - {} releasing...",
+                target.name, target.namespace
+            );
+            let uid = obj.uid();
+            let owner_references = secret
+                .owner_references()
+                .iter()
+                .filter(|x| Some(&x.uid) != uid.as_ref())
+                .collect::<Vec<_>>();
+            let patch = json!({
+                "metadata": {
+                    "ownerReferences": owner_references,
+                    "annotations": {
+                        OWNER_UID_ANNOTATION: null,
+                        OWNER_ANNOTATION: null,
+                    },
+                }
+            });
+            namespace
+                .patch(&target.name, &PatchParams::default(), &Patch::Merge(&patch))
+                .await?;
+            info!("Secret: {} - {} released!", target.name, target.namespace);
+            Ok(())
+        }
+    }
+}
+
+async fn delete_secret(
+    namespace: &Api<Secret>,
+    secret: &impl Resource,
+) -> BitwardenOperatorResult<()> {
+    info!(
+        "Secret: {} - {} deleting...",
+        secret.name_any(),
+        secret.namespace().unwrap_or_default()
+    );
+    // the uid precondition avoids deleting a Secret recreated in the meantime
+    let params = DeleteParams {
+        preconditions: Some(Preconditions {
+            uid: secret.uid(),
+            resource_version: None,
+        }),
+        ..DeleteParams::default()
+    };
+    namespace.delete(&secret.name_any(), &params).await?;
+    info!(
+        "Secret: {} - {} deleted!",
+        secret.name_any(),
+        secret.namespace().unwrap_or_default()
+    );
+    Ok(())
+}
+
+/// Removes the Secrets carrying the operator labels which are not the current target of the
+/// BitwardenSecret controlling them anymore, or whose BitwardenSecret is gone. With sharding, only
+/// the Secrets of the BitwardenSecrets in the shard, or the orphaned Secrets in it, are swept, and
+/// only the Secrets of the watched namespaces when the scope is restricted.
+async fn sweep_orphaned_secrets(ctx: &KubeContext) -> BitwardenOperatorResult<()> {
+    info!("Sweeping orphaned secrets...");
+    let client = &ctx.client;
+    let scope = ctx.scope.settings();
+    let mut bitwarden_secrets = HashMap::new();
+    for api in scope.apis::<BitwardenSecret>(client) {
+        let params = ListParams {
+            label_selector: scope.label_selector.clone(),
+            ..ListParams::default()
+        };
+        let items = api.list(&params).await?.items;
+        bitwarden_secrets.extend(items.into_iter().filter_map(|x| Some((x.uid()?, x))));
+    }
+
+    let params = ListParams::default().labels(&managed_by_selector());
+    let mut secrets = vec![];
+    for api in scope.apis::<Secret>(client) {
+        secrets.extend(api.list_metadata(&params).await?.items);
+    }
+    for secret in secrets {
+        let Some(owner) = owner_uid(&secret) else {
+            continue;
+        };
+
+        let target = SecretTarget {
+            name: secret.name_any(),
+            namespace: secret.namespace().unwrap_or_default(),
+        };
+        let result = match bitwarden_secrets.get(&owner) {
+            Some(bitwarden_secret) if target_secret(bitwarden_secret) == target => continue,
+            Some(bitwarden_secret) if !ctx.owns(bitwarden_secret) => continue,
+            Some(bitwarden_secret) => release_secret(client, bitwarden_secret, &target).await,
+            // the BitwardenSecret may exist out of the scope, e.g. in a namespace not watched
+            None if !scope.is_cluster_wide() || !ctx.owns(&secret) => continue,
+            None => {
+                let namespace = Api::<Secret>::namespaced(client.clone(), &target.namespace);
+                delete_secret(&namespace, &secret).await
+            }
+        };
+        if let Err(e) = result {
+            warn!(
+                "Secret: {} - {} couldn't be swept: {}",
+                target.name, target.namespace, e
+            );
         }
+    }
+    info!("Orphaned secrets swept!");
+    Ok(())
+}
+
+/// Publishes an Event on the resource, failures are only logged as events are best effort
+pub(crate) async fn publish_event<K: Resource<DynamicType = ()>>(
+    ctx: &KubeContext,
+    obj: &K,
+    type_: EventType,
+    reason: &str,
+    note: String,
+) {
+    let recorder = Recorder::new(
+        ctx.client.clone(),
+        ctx.reporter.clone(),
+        obj.object_ref(&()),
+    );
+    let event = Event {
+        type_,
+        reason: reason.to_string(),
+        note: Some(note),
+        action: "Reconcile".to_string(),
+        secondary: None,
+    };
+    if let Err(e) = recorder.publish(event).await {
+        warn!(
+            "{}: {} couldn't publish event: {}",
+            K::kind(&()),
+            obj.name_any(),
+            e
+        );
+    }
+}
+
+/// Writes the whole status of the resource with server-side apply
+pub(crate) async fn patch_status<K>(
+    ctx: &KubeContext,
+    obj: &K,
+    status: impl Serialize,
+) -> BitwardenOperatorResult<()>
+where
+    K: Resource<DynamicType = (), Scope = NamespaceResourceScope>
+        + Clone
+        + DeserializeOwned
+        + Debug,
+{
+    let status = json!({
+        "apiVersion": K::api_version(&()),
+        "kind": K::kind(&()),
+        "status": status,
     });
 
     let namespace = &obj.namespace().unwrap();
-    info!("BitwardenSecret: {} updating status...", obj.name_any());
-    let api = Api::<BitwardenSecret>::namespaced(ctx.client.clone(), namespace);
+    info!("{}: {} updating status...", K::kind(&()), obj.name_any());
+    let api = Api::<K>::namespaced(ctx.client.clone(), namespace);
     api.patch_status(
         &obj.name_any(),
-        &PatchParams::default(),
-        &Patch::Merge(&status),
+        &ctx.apply.get().patch_params(),
+        &Patch::Apply(&status),
     )
     .await?;
-    info!("BitwardenSecret: {} status updated!", obj.name_any());
-
-    metrics::counter!("reconcile_requests_success_total").increment(1);
-    Ok(Action::await_change())
+    info!("{}: {} status updated!", K::kind(&()), obj.name_any());
+    Ok(())
 }
 
 fn error_policy(
@@ -198,3 +1139,75 @@ fn error_policy(
     metrics::counter!("reconcile_errors_total").increment(1);
     Action::requeue(Duration::from_secs(5))
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::operator::controller::{finalizers_without_operator, is_paused, pending_force_sync};
+    use crate::operator::schemas::{
+        BitwardenSecret, BitwardenSecretStatus, FORCE_SYNC_ANNOTATION, OPERATOR_FINALIZER,
+        PAUSED_ANNOTATION,
+    };
+    use std::collections::BTreeMap;
+
+    fn bitwarden_secret(annotations: &[(&str, &str)], force_sync: Option<&str>) -> BitwardenSecret {
+        let mut bitwarden_secret = BitwardenSecret::new("secret", Default::default());
+        bitwarden_secret.metadata.annotations = Some(
+            annotations
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<BTreeMap<_, _>>(),
+        );
+        bitwarden_secret.status = Some(BitwardenSecretStatus {
+            force_sync: force_sync.map(String::from),
+            ..Default::default()
+        });
+        bitwarden_secret
+    }
+
+    #[test]
+    fn force_sync_is_pending_until_its_value_is_handled() {
+        assert_eq!(pending_force_sync(&bitwarden_secret(&[], None)), None);
+        let requested = [(FORCE_SYNC_ANNOTATION, "1")];
+        assert_eq!(
+            pending_force_sync(&bitwarden_secret(&requested, None)).as_deref(),
+            Some("1")
+        );
+        assert_eq!(
+            pending_force_sync(&bitwarden_secret(&requested, Some("1"))),
+            None
+        );
+        let requested_again = [(FORCE_SYNC_ANNOTATION, "2")];
+        assert_eq!(
+            pending_force_sync(&bitwarden_secret(&requested_again, Some("1"))).as_deref(),
+            Some("2")
+        );
+    }
+
+    #[test]
+    fn only_true_pauses() {
+        assert!(is_paused(&bitwarden_secret(
+            &[(PAUSED_ANNOTATION, "true")],
+            None
+        )));
+        assert!(!is_paused(&bitwarden_secret(
+            &[(PAUSED_ANNOTATION, "false")],
+            None
+        )));
+        assert!(!is_paused(&bitwarden_secret(&[], None)));
+    }
+
+    #[test]
+    fn only_the_operator_finalizer_is_released() {
+        let mut bitwarden_secret = bitwarden_secret(&[], None);
+        assert_eq!(finalizers_without_operator(&bitwarden_secret), None);
+
+        bitwarden_secret.metadata.finalizers = Some(vec![
+            "example.com/other".to_string(),
+            OPERATOR_FINALIZER.to_string(),
+        ]);
+        assert_eq!(
+            finalizers_without_operator(&bitwarden_secret),
+            Some(vec!["example.com/other".to_string()])
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use crate::bitwarden_cli::{
+    BitwardenCliClient, BitwardenGenerator, BitwardenItem, BitwardenItemField,
+    BITWARDEN_FIELD_TYPE_HIDDEN,
+};
+use crate::operator::get_bitwarden_id;
+use crate::operator::schemas::{
+    BitwardenSecret, BitwardenSecretError, Charset, ContentEntry, ContentSource, GeneratePolicy,
+    GeneratorType,
+};
+use kube::ResourceExt;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use tracing::info;
+
+const DEFAULT_PASSWORD_LENGTH: u8 = 32;
+const DEFAULT_PASSPHRASE_WORDS: u8 = 5;
+const DEFAULT_PASSPHRASE_SEPARATOR: &str = "-";
+
+/// Converts the policy to the options of `bw generate`, within the bounds accepted by Bitwarden
+pub fn generator(
+    key: &str,
+    policy: &GeneratePolicy,
+) -> Result<BitwardenGenerator, BitwardenSecretError> {
+    let invalid =
+        |reason: &str| BitwardenSecretError::InvalidGeneratePolicy(key.to_string(), reason.into());
+    match policy.generator.unwrap_or_default() {
+        GeneratorType::Password => {
+            let length = policy.length.unwrap_or(DEFAULT_PASSWORD_LENGTH);
+            if !(5..=128).contains(&length) {
+                return Err(invalid("password length must be between 5 and 128"));
+            }
+            let charset = policy
+                .charset
+                .clone()
+                .unwrap_or_else(|| vec![Charset::Uppercase, Charset::Lowercase, Charset::Numbers]);
+            if charset.is_empty() {
+                return Err(invalid("charset must not be empty"));
+            }
+            Ok(BitwardenGenerator::Password {
+                length,
+                uppercase: charset.contains(&Charset::Uppercase),
+                lowercase: charset.contains(&Charset::Lowercase),
+                numbers: charset.contains(&Charset::Numbers),
+                special: charset.contains(&Charset::Special),
+            })
+        }
+        GeneratorType::Passphrase => {
+            let words = policy.length.unwrap_or(DEFAULT_PASSPHRASE_WORDS);
+            if !(3..=20).contains(&words) {
+                return Err(invalid("passphrase length must be between 3 and 20 words"));
+            }
+            if policy.charset.is_some() {
+                return Err(invalid("charset only applies to passwords"));
+            }
+            Ok(BitwardenGenerator::Passphrase {
+                words,
+                separator: policy
+                    .separator
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_PASSPHRASE_SEPARATOR.to_string()),
+            })
+        }
+    }
+}
+
+/// Returns true when the entry is generated into the item created by the operator, i.e. it is
+/// generated or rotated but has no `bitwardenId`
+pub(crate) fn uses_generated_item(
+    entry: &ContentEntry,
+    bitwarden_secret: &BitwardenSecret,
+) -> bool {
+    entry.generate_policy().is_some()
+        && matches!(
+            get_bitwarden_id(entry, bitwarden_secret),
+            Err(BitwardenSecretError::MissingBitwardenId(_))
+        )
+}
+
+/// Name of the item holding the generated credentials without `bitwardenId`
+pub fn generated_item_name(bitwarden_secret: &BitwardenSecret) -> String {
+    format!(
+        "{}/{}",
+        bitwarden_secret.namespace().unwrap_or_default(),
+        bitwarden_secret.name_any()
+    )
+}
+
+/// Finds, or creates, the item holding the generated credentials without `bitwardenId`, and uses
+/// it as `spec.bitwardenId` so the Secret is rendered from it.
+///
+/// The item is looked up by name on every sync rather than recorded in the status, so it is never
+/// created twice when a sync fails halfway.
+pub(crate) async fn resolve_generated_item(
+    cli: &BitwardenCliClient,
+    bitwarden_secret: Arc<BitwardenSecret>,
+) -> Result<Arc<BitwardenSecret>, BitwardenSecretError> {
+    if !bitwarden_secret
+        .spec
+        .content
+        .iter()
+        .any(|x| uses_generated_item(x, &bitwarden_secret))
+    {
+        return Ok(bitwarden_secret);
+    }
+
+    let name = generated_item_name(&bitwarden_secret);
+    let mut items = cli
+        .find_items_by_name(&name)
+        .await
+        .map_err(|e| BitwardenSecretError::BitwardenUnavailable(name.clone(), e.to_string()))?;
+    let item = match items.len() {
+        0 => {
+            let item = cli
+                .create_item(&BitwardenItem::secure_note(name.clone()))
+                .await
+                .map_err(|e| BitwardenSecretError::BitwardenWriteFailed(name, e.to_string()))?;
+            info!(
+                "Bitwarden Item: {} created for generated credentials",
+                item.id
+            );
+            item
+        }
+        1 => items.remove(0),
+        _ => {
+            return Err(BitwardenSecretError::WrongValues(
+                name,
+                "name matches more than one item".to_string(),
+            ))
+        }
+    };
+
+    let mut bitwarden_secret = (*bitwarden_secret).clone();
+    bitwarden_secret.spec.bitwarden_id = Some(item.id);
+    Ok(Arc::new(bitwarden_secret))
+}
+
+/// Returns true when the note or the field read by the entry doesn't exist in the item
+pub fn is_missing(source: &ContentSource, item: &BitwardenItem) -> bool {
+    match source {
+        ContentSource::Value(_) => false,
+        ContentSource::Note(_) => item.note.as_deref().unwrap_or_default().is_empty(),
+        ContentSource::Field(reference) => !item
+            .fields
+            .iter()
+            .flatten()
+            .any(|x| x.name == reference.name),
+    }
+}
+
+/// Writes a generated value where the entry reads it, new fields are hidden
+pub fn set_generated_value(source: &ContentSource, item: &mut BitwardenItem, value: String) {
+    match source {
+        ContentSource::Value(_) => {}
+        ContentSource::Note(_) => item.note = Some(value),
+        ContentSource::Field(reference) => {
+            item.fields
+                .get_or_insert_with(Vec::new)
+                .push(BitwardenItemField {
+                    name: reference.name.clone(),
+                    value,
+                    field_type: Some(BITWARDEN_FIELD_TYPE_HIDDEN),
+                    ..Default::default()
+                })
+        }
+    }
+}
+
+/// Generates the notes and fields missing from the fetched items, for the generated and rotated
+/// entries, and stores them in Bitwarden before the Secret is rendered
+pub(crate) async fn generate_missing_values(
+    cli: &BitwardenCliClient,
+    bitwarden_secret: &BitwardenSecret,
+    fetched: &mut HashMap<String, BitwardenItem>,
+) -> Result<(), BitwardenSecretError> {
+    let mut changed = BTreeSet::<String>::new();
+    for entry in &bitwarden_secret.spec.content {
+        let Some(policy) = entry.generate_policy() else {
+            continue;
+        };
+        let Some(bitwarden_id) = get_bitwarden_id(entry, bitwarden_secret)? else {
+            continue;
+        };
+        let item = fetched
+            .get_mut(&bitwarden_id)
+            .ok_or_else(|| BitwardenSecretError::BitwardenItemNotFound(bitwarden_id.clone()))?;
+        if !is_missing(&entry.source, item) {
+            continue;
+        }
+
+        let key = &entry.kubernetes_secret_key;
+        let value = cli
+            .generate(&generator(key, &policy)?)
+            .await
+            .map_err(|e| BitwardenSecretError::GenerateFailed(key.clone(), e.to_string()))?;
+        set_generated_value(&entry.source, item, value);
+        changed.insert(bitwarden_id);
+    }
+
+    for bitwarden_id in changed {
+        let item = cli
+            .edit_item(&bitwarden_id, &fetched[&bitwarden_id])
+            .await
+            .map_err(|e| {
+                BitwardenSecretError::BitwardenWriteFailed(bitwarden_id.clone(), e.to_string())
+            })?;
+        info!(
+            "Bitwarden Item: {} updated with generated credentials",
+            bitwarden_id
+        );
+        metrics::counter!("generated_credentials_total").increment(1);
+        fetched.insert(bitwarden_id, item);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bitwarden_cli::{BitwardenGenerator, BitwardenItem};
+    use crate::operator::generate::{
+        generator, is_missing, set_generated_value, uses_generated_item,
+    };
+    use crate::operator::schemas::{
+        BitwardenFieldReference, BitwardenItemReference, BitwardenSecret, BitwardenSecretSpec,
+        Charset, ContentEntry, ContentSource, GeneratePolicy, GeneratorType,
+    };
+
+    fn field(name: &str) -> ContentSource {
+        ContentSource::Field(BitwardenFieldReference {
+            bitwarden_id: None,
+            name: name.to_string(),
+        })
+    }
+
+    #[test]
+    fn generator_defaults_and_bounds() {
+        assert_eq!(
+            generator("KEY", &GeneratePolicy::default()).unwrap(),
+            BitwardenGenerator::Password {
+                length: 32,
+                uppercase: true,
+                lowercase: true,
+                numbers: true,
+                special: false,
+            }
+        );
+        let passphrase = GeneratePolicy {
+            generator: Some(GeneratorType::Passphrase),
+            ..Default::default()
+        };
+        assert_eq!(
+            generator("KEY", &passphrase).unwrap(),
+            BitwardenGenerator::Passphrase {
+                words: 5,
+                separator: "-".to_string(),
+            }
+        );
+
+        for invalid in [
+            GeneratePolicy {
+                length: Some(4),
+                ..Default::default()
+            },
+            GeneratePolicy {
+                charset: Some(vec![]),
+                ..Default::default()
+            },
+            GeneratePolicy {
+                charset: Some(vec![Charset::Special]),
+                ..passphrase
+            },
+        ] {
+            assert!(generator("KEY", &invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn missing_values_are_generated_in_place() {
+        let mut item = BitwardenItem::default();
+        let note = ContentSource::Note(BitwardenItemReference::default());
+        assert!(is_missing(&note, &item));
+        assert!(is_missing(&field("password"), &item));
+
+        set_generated_value(&note, &mut item, "note".to_string());
+        set_generated_value(&field("password"), &mut item, "secret".to_string());
+        assert!(!is_missing(&note, &item));
+        assert!(!is_missing(&field("password"), &item));
+        assert!(is_missing(&field("other"), &item));
+        assert_eq!(item.fields.unwrap()[0].value, "secret");
+    }
+
+    #[test]
+    fn only_generated_entries_without_id_use_the_generated_item() {
+        let entry = |generate: bool| ContentEntry {
+            kubernetes_secret_key: "KEY".to_string(),
+            source: field("password"),
+            generate: generate.then(GeneratePolicy::default),
+            rotation: None,
+        };
+        let mut bitwarden_secret = BitwardenSecret::new("test", BitwardenSecretSpec::default());
+        assert!(uses_generated_item(&entry(true), &bitwarden_secret));
+        assert!(!uses_generated_item(&entry(false), &bitwarden_secret));
+
+        bitwarden_secret.spec.bitwarden_id = Some("00000000-0000-0000-0000-000000000000".into());
+        assert!(!uses_generated_item(&entry(true), &bitwarden_secret));
+    }
+}
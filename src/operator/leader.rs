@@ -0,0 +1,281 @@
+use crate::config::Config;
+use crate::operator::controller::DEFAULT_FIELD_MANAGER;
+use crate::operator::refresh::parse_interval;
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::coordination::v1::{Lease, LeaseSpec};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::MicroTime;
+use kube::api::{ObjectMeta, PostParams};
+use kube::{Api, Client};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Settings of the Lease based leader election
+#[derive(Debug, Clone)]
+pub struct LeaderElectionSettings {
+    pub lease_name: String,
+    pub namespace: String,
+    /// holder identity of this replica, the pod name
+    pub identity: String,
+    /// how long the Lease is valid without being renewed, standby replicas take over after it
+    pub lease_duration: Duration,
+    /// how often the Lease is renewed by the leader and checked by standby replicas
+    pub retry_period: Duration,
+}
+
+impl LeaderElectionSettings {
+    /// Returns `None` when leader election is disabled
+    pub fn from_config(config: &Config) -> eyre::Result<Option<Self>> {
+        if config.leader_election != Some(true) {
+            return Ok(None);
+        }
+
+        let settings = Self {
+            lease_name: config
+                .leader_election_lease_name
+                .clone()
+                .unwrap_or_else(|| DEFAULT_FIELD_MANAGER.to_string()),
+            namespace: config
+                .pod_namespace
+                .clone()
+                .ok_or_else(|| eyre::eyre!("leader election requires POD_NAMESPACE"))?,
+            identity: config
+                .pod_name
+                .clone()
+                .ok_or_else(|| eyre::eyre!("leader election requires POD_NAME"))?,
+            lease_duration: match &config.leader_election_lease_duration {
+                Some(x) => parse_interval(x)?,
+                None => Duration::from_secs(15),
+            },
+            retry_period: match &config.leader_election_retry_period {
+                Some(x) => parse_interval(x)?,
+                None => Duration::from_secs(2),
+            },
+        };
+        if settings.retry_period.is_zero() || settings.retry_period >= settings.lease_duration {
+            eyre::bail!(
+                "LEADER_ELECTION_RETRY_PERIOD must be shorter than LEADER_ELECTION_LEASE_DURATION"
+            );
+        }
+        Ok(Some(settings))
+    }
+}
+
+/// Returns true when the Lease is held by another identity and hasn't expired yet
+pub fn is_held_by_other(lease: &Lease, identity: &str, now: DateTime<Utc>) -> bool {
+    let Some(spec) = &lease.spec else {
+        return false;
+    };
+    let holder = spec.holder_identity.as_deref().unwrap_or_default();
+    if holder.is_empty() || holder == identity {
+        return false;
+    }
+    match (&spec.renew_time, spec.lease_duration_seconds) {
+        (Some(renew_time), Some(duration)) => chrono::Duration::try_seconds(duration.into())
+            .is_some_and(|duration| now < renew_time.0 + duration),
+        _ => false,
+    }
+}
+
+/// Lease held by a single identity at a time, renewed while it is held
+#[derive(Clone)]
+pub struct LeaseLock {
+    api: Api<Lease>,
+    name: String,
+    identity: String,
+    lease_duration: Duration,
+    labels: BTreeMap<String, String>,
+}
+
+impl LeaseLock {
+    pub fn new(
+        client: Client,
+        namespace: &str,
+        name: String,
+        identity: String,
+        lease_duration: Duration,
+    ) -> Self {
+        Self {
+            api: Api::namespaced(client, namespace),
+            name,
+            identity,
+            lease_duration,
+            labels: BTreeMap::new(),
+        }
+    }
+
+    /// Labels set on the Lease when it is created
+    pub fn with_labels(mut self, labels: BTreeMap<String, String>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// Acquires the Lease when it is free or expired, or renews it when it is already held.
+    /// Returns whether this identity holds the Lease.
+    pub async fn try_acquire_or_renew(&self) -> kube::Result<bool> {
+        let now = MicroTime(Utc::now());
+        let lease_duration_seconds = Some(self.lease_duration.as_secs().max(1) as i32);
+
+        let Some(mut lease) = self.api.get_opt(&self.name).await? else {
+            let lease = Lease {
+                metadata: ObjectMeta {
+                    name: Some(self.name.clone()),
+                    labels: Some(self.labels.clone()),
+                    ..Default::default()
+                },
+                spec: Some(LeaseSpec {
+                    holder_identity: Some(self.identity.clone()),
+                    lease_duration_seconds,
+                    acquire_time: Some(now.clone()),
+                    renew_time: Some(now),
+                    lease_transitions: Some(0),
+                }),
+            };
+            return ignore_conflict(self.api.create(&PostParams::default(), &lease).await);
+        };
+        if is_held_by_other(&lease, &self.identity, now.0) {
+            return Ok(false);
+        }
+
+        let spec = lease.spec.get_or_insert_with(Default::default);
+        if spec.holder_identity.as_deref() != Some(&self.identity) {
+            spec.holder_identity = Some(self.identity.clone());
+            spec.acquire_time = Some(now.clone());
+            spec.lease_transitions = Some(spec.lease_transitions.unwrap_or_default() + 1);
+        }
+        spec.renew_time = Some(now);
+        spec.lease_duration_seconds = lease_duration_seconds;
+        // the resourceVersion of the read Lease makes concurrent updates fail with a conflict
+        ignore_conflict(
+            self.api
+                .replace(&self.name, &PostParams::default(), &lease)
+                .await,
+        )
+    }
+
+    /// Gives the Lease up when this identity holds it, so another one acquires it right away
+    pub async fn release(&self) -> kube::Result<()> {
+        let Some(mut lease) = self.api.get_opt(&self.name).await? else {
+            return Ok(());
+        };
+        let Some(spec) = lease
+            .spec
+            .as_mut()
+            .filter(|x| x.holder_identity.as_deref() == Some(self.identity.as_str()))
+        else {
+            return Ok(());
+        };
+        spec.holder_identity = None;
+        spec.acquire_time = None;
+        spec.renew_time = None;
+        ignore_conflict(
+            self.api
+                .replace(&self.name, &PostParams::default(), &lease)
+                .await,
+        )
+        .map(|_| ())
+    }
+}
+
+/// A conflict means another identity wrote the Lease first
+fn ignore_conflict(result: kube::Result<Lease>) -> kube::Result<bool> {
+    match result {
+        Ok(_) => Ok(true),
+        Err(kube::Error::Api(e)) if e.code == 409 => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Elects a single replica to run the controllers
+pub struct LeaderElector {
+    lock: LeaseLock,
+    settings: LeaderElectionSettings,
+}
+
+impl LeaderElector {
+    pub fn new(client: Client, settings: LeaderElectionSettings) -> Self {
+        let lock = LeaseLock::new(
+            client,
+            &settings.namespace,
+            settings.lease_name.clone(),
+            settings.identity.clone(),
+            settings.lease_duration,
+        );
+        Self { lock, settings }
+    }
+
+    /// Waits until this replica is the leader
+    pub async fn acquire(&self) {
+        info!(
+            "{} waiting for the leader Lease: {}/{}",
+            self.settings.identity, self.settings.namespace, self.settings.lease_name
+        );
+        loop {
+            match self.lock.try_acquire_or_renew().await {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(e) => warn!("leader election failed: {}", e),
+            }
+            tokio::time::sleep(self.settings.retry_period).await;
+        }
+        info!("{} is the leader", self.settings.identity);
+        metrics::gauge!("leader").set(1.0);
+    }
+
+    /// Renews the Lease, returns once the leadership is lost, i.e. another replica may have taken
+    /// over or the Lease couldn't be renewed before it expired
+    pub async fn hold(&self) {
+        let mut renewed = Instant::now();
+        loop {
+            tokio::time::sleep(self.settings.retry_period).await;
+            match self.lock.try_acquire_or_renew().await {
+                Ok(true) => renewed = Instant::now(),
+                Ok(false) => break,
+                Err(e) => warn!("leader Lease renewal failed: {}", e),
+            }
+            // stop before the Lease expires, a standby replica takes over right after
+            if renewed.elapsed() + self.settings.retry_period >= self.settings.lease_duration {
+                break;
+            }
+        }
+        warn!("{} lost the leadership", self.settings.identity);
+        metrics::gauge!("leader").set(0.0);
+    }
+
+    /// Releases the leadership on shutdown
+    pub async fn release(&self) {
+        match self.lock.release().await {
+            Ok(()) => info!("{} released the leadership", self.settings.identity),
+            Err(e) => warn!("leader Lease couldn't be released: {}", e),
+        }
+        metrics::gauge!("leader").set(0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::operator::leader::is_held_by_other;
+    use chrono::{TimeZone, Utc};
+    use k8s_openapi::api::coordination::v1::{Lease, LeaseSpec};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::MicroTime;
+
+    #[test]
+    fn lease_is_taken_over_once_expired() {
+        let renew_time = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let lease = Lease {
+            spec: Some(LeaseSpec {
+                holder_identity: Some("operator-0".to_string()),
+                lease_duration_seconds: Some(15),
+                renew_time: Some(MicroTime(renew_time)),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let during = renew_time + chrono::Duration::try_seconds(10).unwrap();
+        let after = renew_time + chrono::Duration::try_seconds(15).unwrap();
+        assert!(is_held_by_other(&lease, "operator-1", during));
+        assert!(!is_held_by_other(&lease, "operator-0", during));
+        assert!(!is_held_by_other(&lease, "operator-1", after));
+        assert!(!is_held_by_other(&Lease::default(), "operator-1", during));
+    }
+}
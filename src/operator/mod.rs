@@ -1,24 +1,45 @@
+pub mod certificates;
+pub mod conditions;
 pub mod controller;
+pub mod generate;
+pub mod leader;
+pub mod push;
+pub mod refresh;
+pub mod rotation;
 pub mod schemas;
+pub mod scope;
+pub mod shard;
+pub mod webhook;
 
-use crate::bitwarden_cli::{BitwardenCliClient, BitwardenItem};
+use crate::bitwarden_cli::{BitwardenCliClient, BitwardenError, BitwardenItem};
 use crate::operator::schemas::{
-    BitwardenSecret, BitwardenSecretError, BitwardenSecretSpec, ContentEntry,
+    BitwardenSecret, BitwardenSecretError, ContentEntry, ContentSource, CreationPolicy,
+    SecretTarget,
 };
+use chrono::{DateTime, Utc};
 use k8s_openapi::api::core::v1::Secret;
 use k8s_openapi::ByteString;
+use kube::api::ObjectMeta;
+use kube::runtime::reflector::ObjectRef;
 use kube::{Resource, ResourceExt};
+use sha2::{Digest, Sha256};
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
 
+/// Returns the Bitwarden item a content entry is read from, `None` for literal values
 fn get_bitwarden_id(
     content_entry: &ContentEntry,
     bitwarden_secret: &BitwardenSecret,
-) -> Result<String, BitwardenSecretError> {
-    content_entry
-        .bitwarden_id
+) -> Result<Option<String>, BitwardenSecretError> {
+    let bitwarden_id = match &content_entry.source {
+        ContentSource::Value(_) => return Ok(None),
+        ContentSource::Note(reference) => &reference.bitwarden_id,
+        ContentSource::Field(reference) => &reference.bitwarden_id,
+    };
+    bitwarden_id
         .clone()
         .or_else(|| bitwarden_secret.spec.bitwarden_id.clone())
+        .map(Some)
         .ok_or_else(|| {
             BitwardenSecretError::MissingBitwardenId(content_entry.kubernetes_secret_key.clone())
         })
@@ -26,136 +47,496 @@ fn get_bitwarden_id(
 
 fn get_secret_value(
     content_entry: &ContentEntry,
-    bitwarden_item: &BitwardenItem,
-    bitwarden_id: &str,
+    bitwarden_secret: &BitwardenSecret,
+    fetched: &HashMap<String, BitwardenItem>,
 ) -> Result<String, BitwardenSecretError> {
-    if let Some(value) = &content_entry.kubernetes_secret_value {
-        return Ok(value.clone());
-    }
-
-    if let Some(use_note) = content_entry.bitwarden_use_note {
-        return if use_note {
-            // If bitwarden_use_note is true, try to return the note.
-            bitwarden_item.note.clone().ok_or_else(|| {
-                BitwardenSecretError::WrongValues(
-                    bitwarden_id.to_string(),
-                    "bitwarden_use_note".to_string(),
-                )
-            })
-        } else {
-            // If bitwarden_use_note is false, return an error.
-            Err(BitwardenSecretError::WrongValues(
-                bitwarden_id.to_string(),
-                "bitwarden_use_note".to_string(),
-            ))
+    let Some(bitwarden_id) = get_bitwarden_id(content_entry, bitwarden_secret)? else {
+        return match &content_entry.source {
+            ContentSource::Value(value) => Ok(value.clone()),
+            _ => Err(BitwardenSecretError::MissingBitwardenId(
+                content_entry.kubernetes_secret_key.clone(),
+            )),
         };
+    };
+    let bitwarden_item = fetched.get(&bitwarden_id).ok_or_else(|| {
+        BitwardenSecretError::MissingBitwardenId(content_entry.kubernetes_secret_key.clone())
+    })?;
+
+    match &content_entry.source {
+        ContentSource::Value(value) => Ok(value.clone()),
+        ContentSource::Note(_) => bitwarden_item
+            .note
+            .clone()
+            .ok_or_else(|| BitwardenSecretError::WrongValues(bitwarden_id, "note".to_string())),
+        ContentSource::Field(reference) => bitwarden_item
+            .fields
+            .iter()
+            .flatten()
+            .find(|x| x.name == reference.name)
+            .map(|x| x.value.clone())
+            .ok_or_else(|| {
+                BitwardenSecretError::BitwardenFieldNotFound(bitwarden_id, reference.name.clone())
+            }),
     }
+}
 
-    if let Some(field_name) = &content_entry.bitwarden_secret_field {
-        if let Some(fields) = &bitwarden_item.fields {
-            let item_field = fields
-                .iter()
-                .find(|x| &x.name == field_name)
-                .ok_or_else(|| {
-                    BitwardenSecretError::BitwardenItemNotFound(field_name.to_string())
-                })?;
-            return Ok(item_field.value.clone());
+/// Returns true when the key can be used in a Secret, i.e. matches `[-._a-zA-Z0-9]+`
+fn is_valid_secret_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.len() <= 253
+        && key
+            .chars()
+            .all(|x| x.is_ascii_alphanumeric() || matches!(x, '-' | '_' | '.'))
+}
+
+/// Checks the BitwardenSecret spec without reaching Bitwarden, the same checks run when the
+/// Secret is generated and in the validating webhook
+pub fn validate_bitwarden_secret(
+    bitwarden_secret: &BitwardenSecret,
+) -> Result<(), BitwardenSecretError> {
+    let spec = &bitwarden_secret.spec;
+    let mut keys = HashSet::<&str>::new();
+    for content in &spec.content {
+        let key = &content.kubernetes_secret_key;
+        if !is_valid_secret_key(key) {
+            return Err(BitwardenSecretError::InvalidKey(key.clone()));
+        }
+        if !keys.insert(key) {
+            return Err(BitwardenSecretError::DuplicateKey(key.clone()));
+        }
+        let bitwarden_id = match get_bitwarden_id(content, bitwarden_secret) {
+            // the item is created by the operator
+            Err(BitwardenSecretError::MissingBitwardenId(_))
+                if content.generate_policy().is_some() =>
+            {
+                None
+            }
+            bitwarden_id => bitwarden_id?,
+        };
+        if let Some(policy) = content.generate_policy() {
+            if let ContentSource::Value(_) = &content.source {
+                return Err(BitwardenSecretError::InvalidGeneratePolicy(
+                    key.clone(),
+                    "a literal value can't be generated".to_string(),
+                ));
+            }
+            generate::generator(key, &policy)?;
+        }
+        rotation::validate_rotation(content)?;
+        if let Some(previous_key) = content
+            .rotation
+            .as_ref()
+            .and_then(|x| x.previous_key.as_ref())
+        {
+            if !is_valid_secret_key(previous_key) {
+                return Err(BitwardenSecretError::InvalidKey(previous_key.clone()));
+            }
+            if !keys.insert(previous_key) {
+                return Err(BitwardenSecretError::DuplicateKey(previous_key.clone()));
+            }
+        }
+        if let ContentSource::Field(reference) = &content.source {
+            if reference.name.is_empty() {
+                return Err(BitwardenSecretError::WrongValues(
+                    bitwarden_id.unwrap_or_default(),
+                    "source.field.name".to_string(),
+                ));
+            }
         }
     }
 
-    Err(BitwardenSecretError::WrongValues(
-        bitwarden_id.to_string(),
-        "bitwarden_use_note".to_string(),
-    ))
+    for key in spec.string_data.iter().flat_map(|x| x.keys()) {
+        if !is_valid_secret_key(key) {
+            return Err(BitwardenSecretError::InvalidKey(key.clone()));
+        }
+    }
+
+    if let Some(interval) = &spec.refresh_interval {
+        refresh::parse_interval(interval)?;
+    }
+    if let Some(schedule) = &spec.refresh_schedule {
+        refresh::parse_schedule(schedule)?;
+    }
+    Ok(())
+}
+
+/// Fetches the Bitwarden items referenced by the BitwardenSecret and checks that every referenced
+/// note and field exists, without rendering a Secret. Generated and rotated entries are skipped,
+/// their values are created at sync time.
+pub async fn verify_bitwarden_items(
+    cli: Arc<BitwardenCliClient>,
+    bitwarden_secret: &BitwardenSecret,
+) -> Result<(), BitwardenSecretError> {
+    validate_bitwarden_secret(bitwarden_secret)?;
+    let mut bitwarden_secret = bitwarden_secret.clone();
+    bitwarden_secret
+        .spec
+        .content
+        .retain(|x| x.generate_policy().is_none());
+    let to_fetch = try_get_to_fetch(&bitwarden_secret)?;
+    let fetched = fetch_bitwarden_items(cli, to_fetch).await?;
+    generate_secret_data(&bitwarden_secret, &fetched)?;
+    Ok(())
+}
+
+/// Returns the reference of the Secret rendered from the BitwardenSecret
+pub fn target_secret(bitwarden_secret: &BitwardenSecret) -> SecretTarget {
+    let namespace = bitwarden_secret
+        .spec
+        .namespace
+        .clone()
+        .unwrap_or_else(|| bitwarden_secret.metadata.namespace.clone().unwrap());
+    let name = bitwarden_secret
+        .spec
+        .name
+        .clone()
+        .unwrap_or_else(|| bitwarden_secret.metadata.name.clone().unwrap());
+    SecretTarget { name, namespace }
+}
+
+/// Returns the uid of the BitwardenSecret controlling the Secret, from its owner reference or,
+/// for a Secret written to another namespace, from its owner annotation
+pub fn owner_uid(secret: &impl Resource) -> Option<String> {
+    secret
+        .owner_references()
+        .iter()
+        .find(|x| x.controller == Some(true) && x.kind == BitwardenSecret::kind(&()).as_ref())
+        .map(|x| x.uid.clone())
+        .or_else(|| {
+            secret
+                .annotations()
+                .get(schemas::OWNER_UID_ANNOTATION)
+                .cloned()
+        })
+}
+
+/// Returns the BitwardenSecret controlling the Secret, from its owner reference or, for a Secret
+/// written to another namespace, from its owner annotation
+pub fn secret_owner(secret: &impl Resource) -> Option<ObjectRef<BitwardenSecret>> {
+    match secret
+        .owner_references()
+        .iter()
+        .find(|x| x.controller == Some(true) && x.kind == BitwardenSecret::kind(&()).as_ref())
+    {
+        Some(oref) => Some(ObjectRef::new(&oref.name).within(secret.meta().namespace.as_ref()?)),
+        None => {
+            let owner = secret.annotations().get(schemas::OWNER_ANNOTATION)?;
+            let (namespace, name) = owner.split_once('/')?;
+            Some(ObjectRef::new(name).within(namespace))
+        }
+    }
+}
+
+/// Returns true when the Secret is controlled by the BitwardenSecret
+pub fn is_owned_by(secret: &impl Resource, bitwarden_secret: &BitwardenSecret) -> bool {
+    bitwarden_secret.uid().is_some() && owner_uid(secret) == bitwarden_secret.uid()
+}
+
+/// Secret rendered from a BitwardenSecret, along with the keys rotated in Bitwarden to render it
+pub struct RenderedSecret {
+    pub secret: Secret,
+    pub rotated: Vec<String>,
 }
 
 pub async fn generate_secret_from_bitwarden_secret(
     cli: Arc<BitwardenCliClient>,
     bitwarden_secret: Arc<BitwardenSecret>,
-) -> Result<Secret, BitwardenSecretError> {
+    now: DateTime<Utc>,
+) -> Result<RenderedSecret, BitwardenSecretError> {
     let mut secret = Secret::default();
-    secret.metadata.name = Some(
-        bitwarden_secret
-            .spec
-            .name
-            .clone()
-            .unwrap_or_else(|| bitwarden_secret.metadata.name.clone().unwrap()),
-    );
-    secret.metadata.namespace = Some(
-        bitwarden_secret
-            .spec
-            .namespace
-            .clone()
-            .unwrap_or_else(|| bitwarden_secret.metadata.namespace.clone().unwrap()),
-    );
+    let target = target_secret(&bitwarden_secret);
+    secret.metadata.name = Some(target.name);
+    let same_namespace = bitwarden_secret.metadata.namespace.as_ref() == Some(&target.namespace);
+    secret.metadata.namespace = Some(target.namespace);
 
-    let oref = bitwarden_secret.controller_owner_ref(&()).unwrap();
-    secret.owner_references_mut().push(oref);
+    // manifests rendered outside of the cluster have no uid to reference, and owner references
+    // across namespaces are considered dangling by the garbage collector
+    let mut annotations = BTreeMap::<String, String>::new();
+    if bitwarden_secret.spec.creation_policy.unwrap_or_default() != CreationPolicy::Orphan {
+        match bitwarden_secret.controller_owner_ref(&()) {
+            Some(oref) if same_namespace => secret.owner_references_mut().push(oref),
+            Some(oref) => {
+                annotations.insert(schemas::OWNER_UID_ANNOTATION.to_string(), oref.uid);
+                annotations.insert(
+                    schemas::OWNER_ANNOTATION.to_string(),
+                    format!(
+                        "{}/{}",
+                        bitwarden_secret.namespace().unwrap_or_default(),
+                        oref.name
+                    ),
+                );
+            }
+            None => {}
+        }
+    }
 
-    let global_bitwarden_id = bitwarden_secret.spec.bitwarden_id.clone();
-    let to_fetch = try_get_to_fetch(
-        &bitwarden_secret,
-        &bitwarden_secret.spec,
-        global_bitwarden_id,
-    )?;
+    validate_bitwarden_secret(&bitwarden_secret)?;
+    let bitwarden_secret = generate::resolve_generated_item(&cli, bitwarden_secret).await?;
+    let to_fetch = try_get_to_fetch(&bitwarden_secret)?;
 
     // get all bitwarden needed secrets
-    let mut fetched = HashMap::<String, BitwardenItem>::new();
-    for element in to_fetch {
-        let item = cli
-            .get_item(element.clone())
-            .await
-            .map_err(|_e| BitwardenSecretError::BitwardenItemNotFound(element.clone()))?;
-        fetched.insert(element.clone(), item);
-    }
+    let mut fetched = fetch_bitwarden_items(cli.clone(), to_fetch).await?;
+    generate::generate_missing_values(&cli, &bitwarden_secret, &mut fetched).await?;
+    let rotated = rotation::rotate_due_values(&cli, &bitwarden_secret, &mut fetched, now).await?;
 
-    let secret_data = generate_secret_data(&bitwarden_secret, &mut fetched)?;
-    secret.data = Some(secret_data);
+    let mut secret_data = generate_secret_data(&bitwarden_secret, &fetched)?;
 
-    let mut string_data = BTreeMap::<String, String>::new();
+    // stringData is folded into data, the same way the API server does it, so the rendered
+    // Secret can be compared against the live one
     if let Some(bw_string_data) = &bitwarden_secret.spec.string_data {
         for x in bw_string_data {
-            string_data.insert(x.0.clone(), x.1.clone());
+            secret_data.insert(x.0.clone(), ByteString(x.1.as_bytes().to_vec()));
         }
     }
-    secret.string_data = Some(string_data);
+    secret.data = Some(secret_data);
 
     let mut labels = match secret.metadata.labels {
         Some(ref x) => x.clone(),
         None => BTreeMap::new(),
     };
 
-    let now = chrono::offset::Utc::now();
-    labels.insert(schemas::OPERATOR_HASH_LABEL.to_string(), "test".to_string());
-    labels.insert(
-        schemas::OPERATOR_LAST_UPDATE_LABEL.to_string(),
-        now.timestamp().to_string(),
-    );
-
     if let Some(forwarded_labels) = &bitwarden_secret.spec.labels {
         for label in forwarded_labels {
             labels.insert(label.0.clone(), label.1.clone());
         }
     }
+    labels.insert(
+        schemas::OPERATOR_MANAGED_BY_LABEL.to_string(),
+        schemas::OPERATOR_MANAGED_BY.to_string(),
+    );
     secret.metadata.labels = Some(labels);
-    Ok(secret)
+
+    annotations.insert(
+        schemas::OPERATOR_HASH_ANNOTATION.to_string(),
+        secret_checksum(&secret),
+    );
+    secret.metadata.annotations = Some(annotations);
+    Ok(RenderedSecret { secret, rotated })
+}
+
+/// Checks the creation policy of the BitwardenSecret against the Secret already present in the
+/// cluster, and drops the owner reference from `expected` when the Secret must not be adopted.
+pub fn apply_creation_policy(
+    bitwarden_secret: &BitwardenSecret,
+    expected: &mut Secret,
+    present: Option<&impl Resource>,
+) -> Result<(), BitwardenSecretError> {
+    let Some(present) = present else {
+        return Ok(());
+    };
+
+    let uid = bitwarden_secret.uid();
+    let controller = present
+        .owner_references()
+        .iter()
+        .find(|x| x.controller == Some(true))
+        .map(|x| format!("{}/{}", x.kind, x.name))
+        .or_else(|| owner_uid(present).map(|x| format!("BitwardenSecret with uid {x}")));
+    let owned = is_owned_by(present, bitwarden_secret);
+    let conflict = |reason: String| {
+        Err(BitwardenSecretError::SecretConflict(
+            format!(
+                "{}/{}",
+                present.namespace().unwrap_or_default(),
+                present.name_any()
+            ),
+            reason,
+        ))
+    };
+
+    match bitwarden_secret.spec.creation_policy.unwrap_or_default() {
+        CreationPolicy::Owner => match controller {
+            Some(x) if !owned => conflict(format!("already controlled by {x}")),
+            _ => Ok(()),
+        },
+        CreationPolicy::Merge => {
+            if !owned {
+                expected
+                    .owner_references_mut()
+                    .retain(|x| Some(&x.uid) != uid.as_ref());
+                let annotations = expected.annotations_mut();
+                annotations.remove(schemas::OWNER_UID_ANNOTATION);
+                annotations.remove(schemas::OWNER_ANNOTATION);
+            }
+            Ok(())
+        }
+        CreationPolicy::Orphan => Ok(()),
+        CreationPolicy::None => {
+            if owned {
+                Ok(())
+            } else {
+                conflict("already exists and is not owned by the BitwardenSecret".to_string())
+            }
+        }
+    }
+}
+
+/// Computes a stable checksum over the rendered content of a Secret (type, labels and data).
+pub fn secret_checksum(secret: &Secret) -> String {
+    let mut hasher = Sha256::new();
+    if let Some(secret_type) = &secret.type_ {
+        hasher.update(secret_type.as_bytes());
+    }
+    hasher.update([0]);
+    for (key, value) in secret.metadata.labels.iter().flatten() {
+        hasher.update(key.as_bytes());
+        hasher.update([0]);
+        hasher.update(value.as_bytes());
+        hasher.update([0]);
+    }
+    hasher.update([0]);
+    for (key, value) in secret.data.iter().flatten() {
+        hasher.update(key.as_bytes());
+        hasher.update([0]);
+        hasher.update(&value.0);
+        hasher.update([0]);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// How the live Secret drifted from the content written by the operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecretDrift {
+    Deleted,
+    Modified,
+}
+
+impl SecretDrift {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            SecretDrift::Deleted => "SecretDeleted",
+            SecretDrift::Modified => "SecretModified",
+        }
+    }
+
+    pub fn verb(&self) -> &'static str {
+        match self {
+            SecretDrift::Deleted => "deleted",
+            SecretDrift::Modified => "modified",
+        }
+    }
+}
+
+/// Compares the live Secret with the checksum recorded in the status of the BitwardenSecret. Only
+/// the labels and keys written by the operator are compared, the ones added by others are ignored.
+pub fn detect_drift(
+    bitwarden_secret: &BitwardenSecret,
+    live: Option<&Secret>,
+) -> Option<SecretDrift> {
+    let status = bitwarden_secret.status.as_ref()?;
+    let keys = status.synced_keys.as_ref()?;
+    let Some(live) = live else {
+        return Some(SecretDrift::Deleted);
+    };
+
+    // the labels removed from the spec since the last sync are still the ones written
+    let labels = match &status.synced_labels {
+        Some(labels) => labels.iter().map(String::as_str).collect::<HashSet<_>>(),
+        None => {
+            let mut labels = bitwarden_secret
+                .spec
+                .labels
+                .iter()
+                .flatten()
+                .map(|x| x.0.as_str())
+                .collect::<HashSet<_>>();
+            labels.insert(schemas::OPERATOR_MANAGED_BY_LABEL);
+            labels
+        }
+    };
+    // the type isn't rendered, it is left out as well
+    let mut managed = Secret::default();
+    managed.metadata.labels = Some(
+        live.labels()
+            .iter()
+            .filter(|x| labels.contains(x.0.as_str()))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect(),
+    );
+    managed.data = Some(
+        live.data
+            .iter()
+            .flatten()
+            .filter(|x| keys.contains(x.0))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect(),
+    );
+    (secret_checksum(&managed) != status.checksum).then_some(SecretDrift::Modified)
+}
+
+/// Returns true when the metadata of the live Secret shows it already holds everything the
+/// operator would write.
+///
+/// The rendered type and data are compared through the checksum annotation, so the data of the
+/// live Secret is never needed. Labels, annotations and owner references added by other
+/// controllers are ignored, only the ones rendered by the operator have to be present with the
+/// same value.
+pub fn secret_is_up_to_date(expected: &Secret, present: &ObjectMeta) -> bool {
+    fn is_subset<V: PartialEq>(
+        expected: &Option<BTreeMap<String, V>>,
+        present: &Option<BTreeMap<String, V>>,
+    ) -> bool {
+        expected.iter().flatten().all(|(key, value)| {
+            present
+                .as_ref()
+                .and_then(|present| present.get(key))
+                .is_some_and(|x| x == value)
+        })
+    }
+
+    is_subset(&expected.metadata.labels, &present.labels)
+        && is_subset(&expected.metadata.annotations, &present.annotations)
+        && expected
+            .owner_references()
+            .iter()
+            .all(|x| present.owner_references.iter().flatten().any(|y| x == y))
+}
+
+async fn fetch_bitwarden_items(
+    cli: Arc<BitwardenCliClient>,
+    to_fetch: HashSet<String>,
+) -> Result<HashMap<String, BitwardenItem>, BitwardenSecretError> {
+    let mut fetched = HashMap::<String, BitwardenItem>::new();
+    for element in to_fetch {
+        let item = cli.get_item(element.clone()).await.map_err(|e| match e {
+            BitwardenError::ItemNotFound(_) => {
+                BitwardenSecretError::BitwardenItemNotFound(element.clone())
+            }
+            e => BitwardenSecretError::BitwardenUnavailable(element.clone(), e.to_string()),
+        })?;
+        fetched.insert(element.clone(), item);
+    }
+    Ok(fetched)
 }
 
 fn generate_secret_data(
-    bitwarden_secret: &Arc<BitwardenSecret>,
-    fetched: &mut HashMap<String, BitwardenItem>,
+    bitwarden_secret: &BitwardenSecret,
+    fetched: &HashMap<String, BitwardenItem>,
 ) -> Result<BTreeMap<String, ByteString>, BitwardenSecretError> {
     let mut secret_data = BTreeMap::<String, ByteString>::new();
     for entry in &bitwarden_secret.spec.content {
-        let bitwarden_data = fetched
-            .get(&get_bitwarden_id(entry, bitwarden_secret)?)
-            .ok_or_else(|| {
-                BitwardenSecretError::MissingBitwardenId(entry.kubernetes_secret_key.clone())
-            })?;
-
-        let bitwarden_id = &get_bitwarden_id(entry, bitwarden_secret)?;
-        let secret_value = get_secret_value(entry, bitwarden_data, bitwarden_id)?;
-
+        let secret_value = get_secret_value(entry, bitwarden_secret, fetched)?;
+        if let Some(previous_key) = entry
+            .rotation
+            .as_ref()
+            .and_then(|x| x.previous_key.as_ref())
+        {
+            // without any history, both keys hold the current value
+            let previous_value = match (&entry.source, get_bitwarden_id(entry, bitwarden_secret)?) {
+                (ContentSource::Field(reference), Some(bitwarden_id)) => fetched
+                    .get(&bitwarden_id)
+                    .and_then(|x| rotation::previous_field_value(&reference.name, x)),
+                _ => None,
+            };
+            secret_data.insert(
+                previous_key.clone(),
+                ByteString(
+                    previous_value
+                        .unwrap_or_else(|| secret_value.clone())
+                        .into_bytes(),
+                ),
+            );
+        }
         secret_data.insert(
             entry.kubernetes_secret_key.clone(),
             ByteString(secret_value.as_bytes().to_vec()),
@@ -164,24 +545,263 @@ fn generate_secret_data(
     Ok(secret_data)
 }
 
+/// Returns the Bitwarden items referenced by the BitwardenSecret, literal values don't need any
 fn try_get_to_fetch(
-    bitwarden_secret: &Arc<BitwardenSecret>,
-    bitwarden_spec: &BitwardenSecretSpec,
-    global_bitwarden_id: Option<String>,
+    bitwarden_secret: &BitwardenSecret,
 ) -> Result<HashSet<String>, BitwardenSecretError> {
     let mut to_fetch = HashSet::<String>::new();
+    for content in &bitwarden_secret.spec.content {
+        to_fetch.extend(get_bitwarden_id(content, bitwarden_secret)?);
+    }
+    Ok(to_fetch)
+}
 
-    for content in &bitwarden_spec.content {
-        if content.bitwarden_id.is_none()
-            && content.kubernetes_secret_value.is_none()
-            && global_bitwarden_id.is_none()
-        {
-            return Err(BitwardenSecretError::MissingBitwardenId(
-                content.kubernetes_secret_key.clone(),
-            ));
+#[cfg(test)]
+mod tests {
+    use crate::bitwarden_cli::BitwardenCliClient;
+    use crate::operator::schemas::{
+        BitwardenFieldReference, BitwardenItemReference, BitwardenSecret, BitwardenSecretError,
+        BitwardenSecretSpec, BitwardenSecretStatus, ContentEntry, ContentSource, CreationPolicy,
+        GeneratePolicy, OPERATOR_HASH_ANNOTATION,
+    };
+    use crate::operator::{
+        apply_creation_policy, detect_drift, generate_secret_from_bitwarden_secret, is_owned_by,
+        secret_checksum, secret_is_up_to_date, secret_owner, validate_bitwarden_secret,
+        SecretDrift,
+    };
+    use chrono::Utc;
+    use k8s_openapi::api::core::v1::Secret;
+    use k8s_openapi::ByteString;
+    use kube::runtime::reflector::ObjectRef;
+    use kube::{Resource, ResourceExt};
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::Arc;
+
+    fn secret(value: &str) -> Secret {
+        let mut secret = Secret::default();
+        secret.metadata.labels = Some(BTreeMap::from([("app".to_string(), "x".to_string())]));
+        secret.data = Some(BTreeMap::from([(
+            "KEY".to_string(),
+            ByteString(value.as_bytes().to_vec()),
+        )]));
+        secret.metadata.annotations = Some(BTreeMap::from([(
+            OPERATOR_HASH_ANNOTATION.to_string(),
+            secret_checksum(&secret),
+        )]));
+        secret
+    }
+
+    #[test]
+    fn secret_up_to_date_ignores_foreign_metadata() {
+        let expected = secret("value");
+        let mut present = secret("value");
+        present
+            .metadata
+            .labels
+            .as_mut()
+            .unwrap()
+            .insert("other".to_string(), "label".to_string());
+        present.metadata.resource_version = Some("42".to_string());
+
+        assert!(secret_is_up_to_date(&expected, &present.metadata));
+        assert_eq!(
+            secret_checksum(&expected),
+            secret_checksum(&secret("value"))
+        );
+    }
+
+    #[test]
+    fn secret_out_of_date_when_content_differs() {
+        let expected = secret("value");
+        assert!(!secret_is_up_to_date(&expected, &secret("other").metadata));
+        assert!(!secret_is_up_to_date(&expected, &Default::default()));
+        assert_ne!(
+            secret_checksum(&expected),
+            secret_checksum(&secret("other"))
+        );
+    }
+
+    fn bitwarden_secret(creation_policy: CreationPolicy) -> BitwardenSecret {
+        let mut bitwarden_secret = BitwardenSecret::new(
+            "test",
+            BitwardenSecretSpec {
+                creation_policy: Some(creation_policy),
+                ..Default::default()
+            },
+        );
+        bitwarden_secret.metadata.namespace = Some("default".to_string());
+        bitwarden_secret.metadata.uid = Some("00000000-0000-0000-0000-000000000000".to_string());
+        bitwarden_secret
+    }
+
+    #[test]
+    fn creation_policy_handles_unowned_secret() {
+        let present = secret("value");
+        for (policy, conflict, owner_references) in [
+            (CreationPolicy::Owner, false, 1),
+            (CreationPolicy::Merge, false, 0),
+            (CreationPolicy::None, true, 1),
+        ] {
+            let bitwarden_secret = bitwarden_secret(policy);
+            let mut expected = secret("value");
+            expected.metadata.owner_references =
+                Some(vec![bitwarden_secret.controller_owner_ref(&()).unwrap()]);
+
+            let result = apply_creation_policy(&bitwarden_secret, &mut expected, Some(&present));
+            assert_eq!(
+                matches!(result, Err(BitwardenSecretError::SecretConflict(..))),
+                conflict
+            );
+            assert_eq!(expected.owner_references().len(), owner_references);
         }
+    }
+
+    #[tokio::test]
+    async fn cross_namespace_secret_is_owned_through_annotation() {
+        let mut bitwarden_secret = bitwarden_secret(CreationPolicy::Owner);
+        bitwarden_secret.spec.namespace = Some("other".to_string());
+        bitwarden_secret.spec.content = vec![ContentEntry {
+            kubernetes_secret_key: "KEY".to_string(),
+            source: ContentSource::Value("value".to_string()),
+            generate: None,
+            rotation: None,
+        }];
+        let cli = Arc::new(BitwardenCliClient::from_fixture(vec![]));
+
+        let rendered = generate_secret_from_bitwarden_secret(
+            cli,
+            Arc::new(bitwarden_secret.clone()),
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(rendered.secret.namespace().as_deref(), Some("other"));
+        assert!(rendered.secret.owner_references().is_empty());
+        assert!(is_owned_by(&rendered.secret, &bitwarden_secret));
+        assert!(!is_owned_by(&secret("value"), &bitwarden_secret));
+        assert_eq!(
+            secret_owner(&rendered.secret),
+            Some(ObjectRef::from_obj(&bitwarden_secret))
+        );
+        assert_eq!(secret_owner(&secret("value")), None);
+    }
+
+    #[test]
+    fn validate_rejects_misconfigured_content() {
+        let bitwarden_id = Some("00000000-0000-0000-0000-000000000000".to_string());
+        let note = |key: &str| ContentEntry {
+            kubernetes_secret_key: key.to_string(),
+            source: ContentSource::Note(BitwardenItemReference {
+                bitwarden_id: bitwarden_id.clone(),
+            }),
+            generate: None,
+            rotation: None,
+        };
+        let field = |key: &str, name: &str| ContentEntry {
+            kubernetes_secret_key: key.to_string(),
+            source: ContentSource::Field(BitwardenFieldReference {
+                bitwarden_id: bitwarden_id.clone(),
+                name: name.to_string(),
+            }),
+            generate: None,
+            rotation: None,
+        };
+        let validate = |content: Vec<ContentEntry>| {
+            validate_bitwarden_secret(&BitwardenSecret::new(
+                "test",
+                BitwardenSecretSpec {
+                    content,
+                    ..Default::default()
+                },
+            ))
+        };
+
+        assert!(validate(vec![field("KEY", "field")]).is_ok());
+        assert!(matches!(
+            validate(vec![field("KEY", "")]),
+            Err(BitwardenSecretError::WrongValues(..))
+        ));
+        assert!(matches!(
+            validate(vec![note("KEY"), field("KEY", "x")]),
+            Err(BitwardenSecretError::DuplicateKey(..))
+        ));
+        assert!(matches!(
+            validate(vec![note("MY KEY")]),
+            Err(BitwardenSecretError::InvalidKey(..))
+        ));
+        let missing_id = ContentEntry {
+            kubernetes_secret_key: "KEY".to_string(),
+            source: ContentSource::Note(BitwardenItemReference::default()),
+            generate: None,
+            rotation: None,
+        };
+        assert!(matches!(
+            validate(vec![missing_id.clone()]),
+            Err(BitwardenSecretError::MissingBitwardenId(..))
+        ));
+        // the item holding generated values is created by the operator
+        let generated = ContentEntry {
+            generate: Some(GeneratePolicy::default()),
+            ..missing_id
+        };
+        assert!(validate(vec![generated]).is_ok());
+        let literal = ContentEntry {
+            kubernetes_secret_key: "KEY".to_string(),
+            source: ContentSource::Value("value".to_string()),
+            generate: None,
+            rotation: None,
+        };
+        assert!(validate(vec![literal.clone()]).is_ok());
+        let generated_literal = ContentEntry {
+            generate: Some(GeneratePolicy::default()),
+            ..literal
+        };
+        assert!(matches!(
+            validate(vec![generated_literal]),
+            Err(BitwardenSecretError::InvalidGeneratePolicy(..))
+        ));
+    }
+
+    #[test]
+    fn drift_ignores_content_added_by_others() {
+        let mut bitwarden_secret = bitwarden_secret(CreationPolicy::Merge);
+        bitwarden_secret.spec.labels = Some(HashMap::from([("app".to_string(), "x".to_string())]));
+        let written = secret("value");
+        assert_eq!(detect_drift(&bitwarden_secret, Some(&written)), None);
+
+        bitwarden_secret.status = Some(BitwardenSecretStatus {
+            checksum: secret_checksum(&written),
+            synced_keys: Some(vec!["KEY".to_string()]),
+            ..Default::default()
+        });
+        let mut merged = written.clone();
+        merged
+            .labels_mut()
+            .insert("team".to_string(), "a".to_string());
+        merged
+            .data
+            .get_or_insert_with(Default::default)
+            .insert("OTHER".to_string(), ByteString(b"other".to_vec()));
+        assert_eq!(detect_drift(&bitwarden_secret, Some(&merged)), None);
 
-        to_fetch.insert(get_bitwarden_id(content, bitwarden_secret)?);
+        assert_eq!(
+            detect_drift(&bitwarden_secret, Some(&secret("edited"))),
+            Some(SecretDrift::Modified)
+        );
+        let mut relabelled = written.clone();
+        relabelled.labels_mut().remove("app");
+        assert_eq!(
+            detect_drift(&bitwarden_secret, Some(&relabelled)),
+            Some(SecretDrift::Modified)
+        );
+        assert_eq!(
+            detect_drift(&bitwarden_secret, None),
+            Some(SecretDrift::Deleted)
+        );
+
+        // the label removed from the spec is still on the Secret until the next sync
+        bitwarden_secret.spec.labels = None;
+        bitwarden_secret.status.as_mut().unwrap().synced_labels = Some(vec!["app".to_string()]);
+        assert_eq!(detect_drift(&bitwarden_secret, Some(&written)), None);
     }
-    Ok(to_fetch)
 }
@@ -0,0 +1,597 @@
+use crate::bitwarden_cli::{
+    BitwardenCliClient, BitwardenError, BitwardenItem, BitwardenItemField, BitwardenItemLogin,
+    BITWARDEN_FIELD_TYPE_HIDDEN, BITWARDEN_ITEM_TYPE_LOGIN,
+};
+use crate::operator::conditions::{set_condition, CONDITION_READY, STATUS_FALSE, STATUS_TRUE};
+use crate::operator::controller::{
+    patch_status, publish_event, requeue_at, with_shared_triggers, BitwardenOperatorError,
+    BitwardenOperatorResult, KubeContext, SecretWrite,
+};
+use crate::operator::refresh;
+use crate::operator::schemas::{
+    BitwardenPushSecret, BitwardenSecretError, LoginProperty, PushConflictPolicy, PushTarget,
+    PUSHED_LABEL,
+};
+use chrono::Utc;
+use futures::future::join_all;
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::Secret;
+use kube::api::{Patch, PatchParams};
+use kube::runtime::controller::Action;
+use kube::runtime::events::EventType;
+use kube::runtime::reflector::ObjectRef;
+use kube::runtime::{metadata_watcher, watcher, Controller, WatchStreamExt};
+use kube::{Api, ResourceExt};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// Runs the BitwardenPushSecret controller until the watch stream ends
+pub(crate) async fn run(context: Arc<KubeContext>) {
+    let scope = context.scope.settings();
+    let controllers = scope
+        .apis::<BitwardenPushSecret>(&context.client)
+        .into_iter()
+        .zip(scope.apis::<Secret>(&context.client))
+        .map(|(push_secrets, secrets)| {
+            let controller = Controller::new(push_secrets, scope.watcher_config());
+            // only the metadata of the Secrets labelled when they were first pushed is watched
+            let config = watcher::Config::default().labels(&format!("{PUSHED_LABEL}=true"));
+            let pushed_secrets = metadata_watcher(secrets, config)
+                .default_backoff()
+                .touched_objects();
+            let index = context.pushed_secrets.clone();
+            let controller = controller.watches_stream(pushed_secrets, move |secret| {
+                let namespace = secret.namespace().unwrap_or_default();
+                index.pushing(&ObjectRef::new(&secret.name_any()).within(&namespace))
+            });
+            with_shared_triggers(controller, &context)
+                .run(reconcile_push_secret, error_policy, context.clone())
+                .for_each(|res| async move {
+                    match res {
+                        Ok(o) => info!("pushed {}:{}", o.0.namespace.unwrap(), o.0.name),
+                        Err(e) => warn!("push failed: {}", e),
+                    }
+                })
+        });
+    join_all(controllers).await;
+}
+
+/// BitwardenPushSecrets by the Secret they push, recorded when they are reconciled so a change of
+/// the Secret is mapped to them without going through every BitwardenPushSecret
+#[derive(Clone, Default)]
+pub(crate) struct PushedSecrets {
+    index: Arc<Mutex<PushedSecretsIndex>>,
+}
+
+#[derive(Default)]
+struct PushedSecretsIndex {
+    pushing: HashMap<ObjectRef<Secret>, HashSet<ObjectRef<BitwardenPushSecret>>>,
+    pushed: HashMap<ObjectRef<BitwardenPushSecret>, ObjectRef<Secret>>,
+}
+
+impl PushedSecrets {
+    /// Records the Secret pushed by the BitwardenPushSecret, replacing the one it pushed before
+    pub(crate) fn record(&self, push_secret: &BitwardenPushSecret) {
+        let push_ref = ObjectRef::from_obj(push_secret);
+        let secret_ref = ObjectRef::new(&push_secret.spec.secret_name)
+            .within(&push_secret.namespace().unwrap_or_default());
+        let mut index = self.index.lock().unwrap();
+        if let Some(previous) = index.pushed.insert(push_ref.clone(), secret_ref.clone()) {
+            if let Some(x) = index.pushing.get_mut(&previous) {
+                x.remove(&push_ref);
+                if x.is_empty() {
+                    index.pushing.remove(&previous);
+                }
+            }
+        }
+        index
+            .pushing
+            .entry(secret_ref)
+            .or_default()
+            .insert(push_ref);
+    }
+
+    /// BitwardenPushSecrets pushing the Secret
+    pub(crate) fn pushing(
+        &self,
+        secret: &ObjectRef<Secret>,
+    ) -> Vec<ObjectRef<BitwardenPushSecret>> {
+        let index = self.index.lock().unwrap();
+        index
+            .pushing
+            .get(secret)
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect()
+    }
+}
+
+/// Returns the item the BitwardenPushSecret writes into, `None` when it has to be created
+pub fn pushed_item_id(push_secret: &BitwardenPushSecret) -> Option<String> {
+    push_secret.spec.bitwarden_id.clone().or_else(|| {
+        push_secret
+            .status
+            .as_ref()
+            .and_then(|x| x.bitwarden_id.clone())
+    })
+}
+
+/// Name of the item created when the BitwardenPushSecret doesn't reference one
+pub fn pushed_item_name(push_secret: &BitwardenPushSecret) -> String {
+    push_secret.spec.item_name.clone().unwrap_or_else(|| {
+        format!(
+            "{}/{}",
+            push_secret.namespace().unwrap_or_default(),
+            push_secret.name_any()
+        )
+    })
+}
+
+/// Builds the item created when the BitwardenPushSecret doesn't reference one yet, a login when
+/// any login property is pushed, a secure note otherwise
+pub fn new_pushed_item(push_secret: &BitwardenPushSecret) -> BitwardenItem {
+    let mut item = BitwardenItem::secure_note(pushed_item_name(push_secret));
+    let pushes_login = push_secret
+        .spec
+        .data
+        .iter()
+        .any(|x| matches!(x.target, PushTarget::Login(_)));
+    if pushes_login {
+        item.item_type = Some(BITWARDEN_ITEM_TYPE_LOGIN);
+        item.login = Some(BitwardenItemLogin::default());
+        item.other.remove("secureNote");
+    }
+    item
+}
+
+/// Writes the selected keys of the Secret into the item, returns true when the item changed
+pub fn render_pushed_item(
+    push_secret: &BitwardenPushSecret,
+    secret: &Secret,
+    item: &mut BitwardenItem,
+) -> Result<bool, BitwardenSecretError> {
+    let before = item.clone();
+    let secret_name = secret.name_any();
+    for entry in &push_secret.spec.data {
+        let value = secret
+            .data
+            .as_ref()
+            .and_then(|x| x.get(&entry.secret_key))
+            .ok_or_else(|| {
+                BitwardenSecretError::SecretKeyNotFound(
+                    secret_name.clone(),
+                    entry.secret_key.clone(),
+                )
+            })?;
+        let value = String::from_utf8(value.0.clone()).map_err(|_| {
+            BitwardenSecretError::InvalidSecretValue(secret_name.clone(), entry.secret_key.clone())
+        })?;
+
+        match &entry.target {
+            PushTarget::Note(_) => item.note = Some(value),
+            PushTarget::Field(target) => {
+                let fields = item.fields.get_or_insert_with(Vec::new);
+                match fields.iter_mut().find(|x| x.name == target.name) {
+                    Some(field) => field.value = value,
+                    None => fields.push(BitwardenItemField {
+                        name: target.name.clone(),
+                        value,
+                        field_type: Some(BITWARDEN_FIELD_TYPE_HIDDEN),
+                        ..Default::default()
+                    }),
+                }
+            }
+            PushTarget::Login(target) => {
+                let Some(login) = item
+                    .login
+                    .as_mut()
+                    .filter(|_| item.item_type == Some(BITWARDEN_ITEM_TYPE_LOGIN))
+                else {
+                    return Err(BitwardenSecretError::WrongValues(
+                        item.id.clone(),
+                        "login".to_string(),
+                    ));
+                };
+                let property = match target.property {
+                    LoginProperty::Username => &mut login.username,
+                    LoginProperty::Password => &mut login.password,
+                    LoginProperty::Totp => &mut login.totp,
+                };
+                *property = Some(value);
+            }
+        }
+    }
+    Ok(*item != before)
+}
+
+/// Returns why the item must not be overwritten, when it was modified in Bitwarden since the
+/// operator last wrote it
+pub fn pushed_item_conflict(
+    push_secret: &BitwardenPushSecret,
+    item: &BitwardenItem,
+) -> Option<String> {
+    let status = push_secret.status.as_ref()?;
+    // the revision is only known for the item written by the last push
+    if status.bitwarden_id.as_deref() != Some(item.id.as_str()) {
+        return None;
+    }
+    let pushed = status.revision_date?;
+    match item.revision_date {
+        Some(current) if current != pushed => Some(format!(
+            "modified at {current}, the last push wrote revision {pushed}"
+        )),
+        _ => None,
+    }
+}
+
+/// Outcome of a successful push
+struct PushedItem {
+    item: BitwardenItem,
+    write: SecretWrite,
+}
+
+/// Looks up the item created by a previous push by its name, so a push failing before the id of
+/// the item is recorded in the status never creates it twice
+async fn find_pushed_item(
+    cli: &BitwardenCliClient,
+    push_secret: &BitwardenPushSecret,
+) -> Result<Option<BitwardenItem>, BitwardenSecretError> {
+    let name = pushed_item_name(push_secret);
+    let mut items = cli
+        .find_items_by_name(&name)
+        .await
+        .map_err(|e| BitwardenSecretError::BitwardenUnavailable(name.clone(), e.to_string()))?;
+    match items.len() {
+        0 => Ok(None),
+        1 => Ok(Some(items.remove(0))),
+        _ => Err(BitwardenSecretError::WrongValues(
+            name,
+            "name matches more than one item".to_string(),
+        )),
+    }
+}
+
+async fn push_secret(
+    obj: &BitwardenPushSecret,
+    ctx: &KubeContext,
+) -> BitwardenOperatorResult<PushedItem> {
+    let namespace = obj.namespace().unwrap();
+    let secret = Api::<Secret>::namespaced(ctx.client.clone(), &namespace)
+        .get_opt(&obj.spec.secret_name)
+        .await?
+        .ok_or_else(|| {
+            BitwardenSecretError::SecretNotFound(format!("{}/{}", namespace, obj.spec.secret_name))
+        })?;
+    // the label lets the Secret be watched, so its changes are pushed right away
+    if secret.labels().get(PUSHED_LABEL).map(String::as_str) != Some("true") {
+        let patch = json!({ "metadata": { "labels": { PUSHED_LABEL: "true" } } });
+        Api::<Secret>::namespaced(ctx.client.clone(), &namespace)
+            .patch(
+                &secret.name_any(),
+                &PatchParams::default(),
+                &Patch::Merge(&patch),
+            )
+            .await?;
+    }
+
+    let cli = &ctx.bitwarden_cli;
+    let mut item = match pushed_item_id(obj) {
+        Some(item_id) => cli.get_item(item_id.clone()).await.map_err(|e| match e {
+            BitwardenError::ItemNotFound(_) => {
+                BitwardenSecretError::BitwardenItemNotFound(item_id.clone())
+            }
+            e => BitwardenSecretError::BitwardenUnavailable(item_id.clone(), e.to_string()),
+        })?,
+        None => match find_pushed_item(cli, obj).await? {
+            Some(item) => item,
+            None => {
+                let mut item = new_pushed_item(obj);
+                render_pushed_item(obj, &secret, &mut item)?;
+                let item = cli.create_item(&item).await.map_err(|e| {
+                    BitwardenSecretError::BitwardenWriteFailed(
+                        item.name.clone().unwrap_or_default(),
+                        e.to_string(),
+                    )
+                })?;
+                return Ok(PushedItem {
+                    item,
+                    write: SecretWrite::Created,
+                });
+            }
+        },
+    };
+    let item_id = item.id.clone();
+    let conflict = pushed_item_conflict(obj, &item);
+    if !render_pushed_item(obj, &secret, &mut item)? {
+        return Ok(PushedItem {
+            item,
+            write: SecretWrite::Unchanged,
+        });
+    }
+
+    if let Some(conflict) = conflict {
+        if obj.spec.conflict_policy.unwrap_or_default() == PushConflictPolicy::Fail {
+            return Err(BitwardenSecretError::ItemConflict(item_id, conflict).into());
+        }
+        warn!("Bitwarden Item: {} overwritten: {}", item_id, conflict);
+    }
+    let item = cli
+        .edit_item(&item_id, &item)
+        .await
+        .map_err(|e| BitwardenSecretError::BitwardenWriteFailed(item_id, e.to_string()))?;
+    Ok(PushedItem {
+        item,
+        write: SecretWrite::Updated,
+    })
+}
+
+async fn reconcile_push_secret(
+    obj: Arc<BitwardenPushSecret>,
+    ctx: Arc<KubeContext>,
+) -> BitwardenOperatorResult<Action> {
+    if !ctx.owns(&*obj) {
+        return Ok(Action::await_change());
+    }
+    info!("push request: {}", obj.name_any());
+    metrics::counter!("push_requests_total").increment(1);
+    ctx.pushed_secrets.record(&obj);
+
+    let interval = match &obj.spec.refresh_interval {
+        Some(interval) => refresh::parse_interval(interval)?,
+        None => ctx.refresh.get().interval,
+    };
+    let result = push_secret(&obj, &ctx).await;
+
+    let generation = obj.metadata.generation;
+    let mut status = obj.status.clone().unwrap_or_default();
+    status.observed_generation = generation;
+    status.conflict = None;
+    let conditions = status.conditions.get_or_insert_with(Vec::new);
+    let action = match result {
+        Ok(pushed) => {
+            let keys = obj
+                .spec
+                .data
+                .iter()
+                .map(|x| x.secret_key.clone())
+                .collect::<Vec<_>>();
+            let note = format!(
+                "Bitwarden Item {} {} with keys: {}",
+                pushed.item.id,
+                pushed.write.verb(),
+                keys.join(", ")
+            );
+            publish_event(&ctx, &*obj, EventType::Normal, pushed.write.reason(), note).await;
+            set_condition(
+                conditions,
+                CONDITION_READY,
+                STATUS_TRUE,
+                "Pushed",
+                format!("Bitwarden Item {} is up to date", pushed.item.id),
+                generation,
+            );
+            if pushed.write != SecretWrite::Unchanged {
+                status.last_pushed = Some(Utc::now());
+            }
+            status.bitwarden_id = Some(pushed.item.id);
+            status.revision_date = pushed.item.revision_date;
+            status.pushed_keys = Some(keys);
+            Ok(if interval.is_zero() {
+                Action::await_change()
+            } else {
+                chrono::Duration::from_std(interval)
+                    .ok()
+                    .and_then(|x| Utc::now().checked_add_signed(x))
+                    .map_or_else(Action::await_change, requeue_at)
+            })
+        }
+        Err(e) => {
+            error!(
+                "Failed to push BitwardenPushSecret: {}, {}",
+                obj.name_any(),
+                e
+            );
+            let reason = match &e {
+                BitwardenOperatorError::BitwardenSecretError(x) => {
+                    if let BitwardenSecretError::ItemConflict(..) = x {
+                        status.conflict = Some(x.to_string());
+                    }
+                    x.reason()
+                }
+                _ => "PushFailed",
+            };
+            publish_event(&ctx, &*obj, EventType::Warning, reason, e.to_string()).await;
+            set_condition(
+                conditions,
+                CONDITION_READY,
+                STATUS_FALSE,
+                reason,
+                e.to_string(),
+                generation,
+            );
+            Err(e)
+        }
+    };
+    patch_status(&ctx, &*obj, status).await?;
+    action
+}
+
+fn error_policy(
+    _object: Arc<BitwardenPushSecret>,
+    err: &BitwardenOperatorError,
+    _ctx: Arc<KubeContext>,
+) -> Action {
+    metrics::counter!("push_errors_total").increment(1);
+    // a conflict needs a human decision, retrying right away would only flood the events
+    match err {
+        BitwardenOperatorError::BitwardenSecretError(BitwardenSecretError::ItemConflict(..)) => {
+            Action::requeue(Duration::from_secs(300))
+        }
+        _ => Action::requeue(Duration::from_secs(5)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bitwarden_cli::{BitwardenItem, BitwardenItemField, BITWARDEN_ITEM_TYPE_LOGIN};
+    use crate::operator::push::{
+        new_pushed_item, pushed_item_conflict, render_pushed_item, PushedSecrets,
+    };
+    use crate::operator::schemas::{
+        BitwardenPushSecret, BitwardenPushSecretSpec, BitwardenPushSecretStatus, LoginProperty,
+        PushEntry, PushFieldTarget, PushLoginTarget, PushNoteTarget, PushTarget,
+    };
+    use chrono::{TimeZone, Utc};
+    use k8s_openapi::api::core::v1::Secret;
+    use k8s_openapi::ByteString;
+    use kube::runtime::reflector::ObjectRef;
+    use std::collections::BTreeMap;
+
+    fn push_secret(data: Vec<(&str, PushTarget)>) -> BitwardenPushSecret {
+        let mut push_secret = BitwardenPushSecret::new(
+            "database",
+            BitwardenPushSecretSpec {
+                secret_name: "database".to_string(),
+                data: data
+                    .into_iter()
+                    .map(|(key, target)| PushEntry {
+                        secret_key: key.to_string(),
+                        target,
+                    })
+                    .collect(),
+                ..Default::default()
+            },
+        );
+        push_secret.metadata.namespace = Some("default".to_string());
+        push_secret
+    }
+
+    fn secret() -> Secret {
+        Secret {
+            data: Some(BTreeMap::from([
+                ("username".to_string(), ByteString(b"admin".to_vec())),
+                ("password".to_string(), ByteString(b"hunter2".to_vec())),
+            ])),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn render_writes_fields_notes_and_login() {
+        let push_secret = push_secret(vec![
+            (
+                "username",
+                PushTarget::Login(PushLoginTarget {
+                    property: LoginProperty::Username,
+                }),
+            ),
+            (
+                "password",
+                PushTarget::Field(PushFieldTarget {
+                    name: "password".to_string(),
+                }),
+            ),
+            ("password", PushTarget::Note(PushNoteTarget {})),
+        ]);
+        let mut item = new_pushed_item(&push_secret);
+        assert_eq!(item.name.as_deref(), Some("default/database"));
+        assert_eq!(item.item_type, Some(BITWARDEN_ITEM_TYPE_LOGIN));
+
+        assert!(render_pushed_item(&push_secret, &secret(), &mut item).unwrap());
+        assert_eq!(
+            item.login.as_ref().unwrap().username.as_deref(),
+            Some("admin")
+        );
+        assert_eq!(item.fields.as_ref().unwrap()[0].value, "hunter2");
+        assert_eq!(item.note.as_deref(), Some("hunter2"));
+
+        // rendering again is a no-op
+        assert!(!render_pushed_item(&push_secret, &secret(), &mut item).unwrap());
+    }
+
+    #[test]
+    fn render_keeps_other_fields_and_rejects_login_on_notes() {
+        let field = |name: &str, value: &str| BitwardenItemField {
+            name: name.to_string(),
+            value: value.to_string(),
+            ..Default::default()
+        };
+        let mut item = BitwardenItem {
+            id: "00000000-0000-0000-0000-000000000000".to_string(),
+            fields: Some(vec![field("other", "x"), field("password", "old")]),
+            ..Default::default()
+        };
+        let push_field = push_secret(vec![(
+            "password",
+            PushTarget::Field(PushFieldTarget {
+                name: "password".to_string(),
+            }),
+        )]);
+        assert!(render_pushed_item(&push_field, &secret(), &mut item).unwrap());
+        assert_eq!(
+            item.fields,
+            Some(vec![field("other", "x"), field("password", "hunter2")])
+        );
+
+        let push_login = push_secret(vec![(
+            "password",
+            PushTarget::Login(PushLoginTarget {
+                property: LoginProperty::Password,
+            }),
+        )]);
+        assert!(render_pushed_item(&push_login, &secret(), &mut item).is_err());
+    }
+
+    #[test]
+    fn pushed_secrets_are_indexed_by_secret() {
+        let index = PushedSecrets::default();
+        let mut push_secret = push_secret(vec![]);
+        let database = ObjectRef::new("database").within("default");
+        index.record(&push_secret);
+        assert_eq!(
+            index.pushing(&database),
+            vec![ObjectRef::from_obj(&push_secret)]
+        );
+        assert!(index
+            .pushing(&ObjectRef::new("database").within("other"))
+            .is_empty());
+
+        // the Secret pushed before is not mapped to the BitwardenPushSecret anymore
+        push_secret.spec.secret_name = "cache".to_string();
+        index.record(&push_secret);
+        assert!(index.pushing(&database).is_empty());
+        assert_eq!(
+            index
+                .pushing(&ObjectRef::new("cache").within("default"))
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn conflict_when_item_changed_since_last_push() {
+        let pushed = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let mut push_secret = push_secret(vec![]);
+        push_secret.status = Some(BitwardenPushSecretStatus {
+            bitwarden_id: Some("00000000-0000-0000-0000-000000000000".to_string()),
+            revision_date: Some(pushed),
+            ..Default::default()
+        });
+        let mut item = BitwardenItem {
+            id: "00000000-0000-0000-0000-000000000000".to_string(),
+            revision_date: Some(pushed),
+            ..Default::default()
+        };
+        assert_eq!(pushed_item_conflict(&push_secret, &item), None);
+
+        item.revision_date = Some(Utc.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap());
+        assert!(pushed_item_conflict(&push_secret, &item).is_some());
+
+        item.id = "11111111-1111-1111-1111-111111111111".to_string();
+        assert_eq!(pushed_item_conflict(&push_secret, &item), None);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::config::Config;
+use crate::operator::schemas::{BitwardenSecret, BitwardenSecretError};
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Operator-wide refresh defaults, used when a BitwardenSecret doesn't specify its own
+#[derive(Debug, Clone)]
+pub struct RefreshSettings {
+    pub interval: Duration,
+    pub schedule: Option<Schedule>,
+}
+
+impl Default for RefreshSettings {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(3600),
+            schedule: None,
+        }
+    }
+}
+
+impl RefreshSettings {
+    pub fn from_config(config: &Config) -> eyre::Result<Self> {
+        let mut settings = RefreshSettings::default();
+        if let Some(interval) = &config.default_refresh_interval {
+            settings.interval = parse_interval(interval)?;
+        }
+        if let Some(schedule) = &config.default_refresh_schedule {
+            settings.schedule = Some(parse_schedule(schedule)?);
+        }
+        Ok(settings)
+    }
+
+    /// Computes when the BitwardenSecret has to be refreshed after a sync done at `last_sync`,
+    /// `None` means it is only refreshed when it changes
+    pub fn next_refresh(
+        &self,
+        bitwarden_secret: &BitwardenSecret,
+        last_sync: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>, BitwardenSecretError> {
+        let interval = match &bitwarden_secret.spec.refresh_interval {
+            Some(interval) => parse_interval(interval)?,
+            None => self.interval,
+        };
+        let schedule = match &bitwarden_secret.spec.refresh_schedule {
+            Some(schedule) => Some(parse_schedule(schedule)?),
+            None => self.schedule.clone(),
+        };
+
+        let by_interval = (!interval.is_zero())
+            .then(|| chrono::Duration::from_std(interval).ok())
+            .flatten()
+            .and_then(|x| last_sync.checked_add_signed(x));
+        let by_schedule = schedule.and_then(|x| x.after(&last_sync).next());
+
+        Ok(match (by_interval, by_schedule) {
+            (Some(x), Some(y)) => Some(x.min(y)),
+            (x, y) => x.or(y),
+        })
+    }
+}
+
+/// Parses a refresh interval such as `1h`, `15m` or `1h 30m`, `0` disables periodic refresh
+pub fn parse_interval(interval: &str) -> Result<Duration, BitwardenSecretError> {
+    if interval.trim() == "0" {
+        return Ok(Duration::ZERO);
+    }
+    humantime::parse_duration(interval)
+        .map_err(|e| BitwardenSecretError::InvalidRefresh(format!("{interval}: {e}")))
+}
+
+/// Parses a cron expression, the standard 5 fields format is accepted as well as the 6 and 7
+/// fields formats with seconds and years
+pub fn parse_schedule(schedule: &str) -> Result<Schedule, BitwardenSecretError> {
+    let expression = if schedule.split_whitespace().count() == 5 {
+        format!("0 {schedule}")
+    } else {
+        schedule.to_string()
+    };
+    Schedule::from_str(&expression)
+        .map_err(|e| BitwardenSecretError::InvalidRefresh(format!("{schedule}: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::operator::refresh::{parse_interval, RefreshSettings};
+    use crate::operator::schemas::{BitwardenSecret, BitwardenSecretSpec};
+    use chrono::{TimeZone, Utc};
+    use std::time::Duration;
+
+    fn bitwarden_secret(interval: Option<&str>, schedule: Option<&str>) -> BitwardenSecret {
+        BitwardenSecret::new(
+            "test",
+            BitwardenSecretSpec {
+                refresh_interval: interval.map(str::to_string),
+                refresh_schedule: schedule.map(str::to_string),
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn parse_refresh_interval() {
+        assert_eq!(parse_interval("0").unwrap(), Duration::ZERO);
+        assert_eq!(parse_interval("1h 30m").unwrap(), Duration::from_secs(5400));
+        assert!(parse_interval("often").is_err());
+    }
+
+    #[test]
+    fn next_refresh_picks_the_earliest() {
+        let settings = RefreshSettings::default();
+        let last_sync = Utc.with_ymd_and_hms(2024, 1, 1, 10, 20, 0).unwrap();
+
+        let next = settings.next_refresh(&bitwarden_secret(None, None), last_sync);
+        assert_eq!(next.unwrap(), Some(last_sync + Duration::from_secs(3600)));
+
+        let next = settings.next_refresh(&bitwarden_secret(Some("0"), None), last_sync);
+        assert_eq!(next.unwrap(), None);
+
+        let next = settings.next_refresh(&bitwarden_secret(None, Some("30 * * * *")), last_sync);
+        assert_eq!(
+            next.unwrap(),
+            Some(Utc.with_ymd_and_hms(2024, 1, 1, 10, 30, 0).unwrap())
+        );
+
+        let next =
+            settings.next_refresh(&bitwarden_secret(Some("0"), Some("0 0 * * *")), last_sync);
+        assert_eq!(
+            next.unwrap(),
+            Some(Utc.with_ymd_and_hms(2024, 1, 2, 0, 0, 0).unwrap())
+        );
+    }
+}
@@ -0,0 +1,272 @@
+use crate::bitwarden_cli::{BitwardenCliClient, BitwardenItem};
+use crate::operator::generate::generator;
+use crate::operator::get_bitwarden_id;
+use crate::operator::refresh::parse_interval;
+use crate::operator::schemas::{
+    BitwardenSecret, BitwardenSecretError, ContentEntry, ContentSource,
+};
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use tracing::info;
+
+/// Time between two rotations of the entry, `None` when it isn't rotated
+pub fn rotation_interval(
+    entry: &ContentEntry,
+) -> Result<Option<chrono::Duration>, BitwardenSecretError> {
+    let Some(rotation) = &entry.rotation else {
+        return Ok(None);
+    };
+    let invalid = |reason: String| {
+        BitwardenSecretError::InvalidRotationPolicy(entry.kubernetes_secret_key.clone(), reason)
+    };
+    let interval = parse_interval(&rotation.interval).map_err(|e| invalid(e.to_string()))?;
+    if interval.is_zero() {
+        return Err(invalid("interval must not be 0".to_string()));
+    }
+    chrono::Duration::from_std(interval)
+        .map(Some)
+        .map_err(|e| invalid(e.to_string()))
+}
+
+/// Checks the rotation policy of the entry, only fields are rotated since notes don't keep a
+/// history in Bitwarden
+pub fn validate_rotation(entry: &ContentEntry) -> Result<(), BitwardenSecretError> {
+    if entry.rotation.is_some() && !matches!(entry.source, ContentSource::Field(_)) {
+        return Err(BitwardenSecretError::InvalidRotationPolicy(
+            entry.kubernetes_secret_key.clone(),
+            "only fields can be rotated".to_string(),
+        ));
+    }
+    rotation_interval(entry)?;
+    Ok(())
+}
+
+/// Returns true when the entry was last rotated at least one interval ago. A key never rotated is
+/// not due, its first rotation happens one interval after its first sync.
+pub fn is_rotation_due(
+    entry: &ContentEntry,
+    last_rotated: &BTreeMap<String, DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Result<bool, BitwardenSecretError> {
+    let Some(interval) = rotation_interval(entry)? else {
+        return Ok(false);
+    };
+    Ok(last_rotated
+        .get(&entry.kubernetes_secret_key)
+        .is_some_and(|x| *x + interval <= now))
+}
+
+/// Records the rotations done by a sync at `now` in `status.lastRotated`, keys seen for the first
+/// time start their schedule and keys not rotated anymore are dropped
+pub fn record_rotations(
+    bitwarden_secret: &BitwardenSecret,
+    last_rotated: &mut BTreeMap<String, DateTime<Utc>>,
+    rotated: &[String],
+    now: DateTime<Utc>,
+) {
+    let rotated_keys = bitwarden_secret
+        .spec
+        .content
+        .iter()
+        .filter(|x| x.rotation.is_some())
+        .map(|x| &x.kubernetes_secret_key)
+        .collect::<BTreeSet<_>>();
+    last_rotated.retain(|key, _| rotated_keys.contains(key));
+    for key in rotated_keys {
+        if rotated.contains(key) || !last_rotated.contains_key(key) {
+            last_rotated.insert(key.clone(), now);
+        }
+    }
+}
+
+/// Returns when the next entry has to be rotated, according to `status.lastRotated`
+pub fn next_rotation(
+    bitwarden_secret: &BitwardenSecret,
+    last_rotated: &BTreeMap<String, DateTime<Utc>>,
+) -> Result<Option<DateTime<Utc>>, BitwardenSecretError> {
+    let mut next = None::<DateTime<Utc>>;
+    for entry in &bitwarden_secret.spec.content {
+        let Some(interval) = rotation_interval(entry)? else {
+            continue;
+        };
+        if let Some(last) = last_rotated.get(&entry.kubernetes_secret_key) {
+            let due = *last + interval;
+            next = Some(next.map_or(due, |x| x.min(due)));
+        }
+    }
+    Ok(next)
+}
+
+/// Value of the field before its last change, read from the item `passwordHistory`
+pub fn previous_field_value(name: &str, item: &BitwardenItem) -> Option<String> {
+    let prefix = format!("{name}: ");
+    item.password_history
+        .iter()
+        .flatten()
+        .filter_map(|x| Some((x.last_used_date, x.password.strip_prefix(&prefix)?)))
+        .max_by_key(|x| x.0)
+        .map(|x| x.1.to_string())
+}
+
+/// Replaces the fields due for rotation with generated values and writes them to Bitwarden, which
+/// moves the previous values to the item `passwordHistory`. Returns the rotated keys.
+pub(crate) async fn rotate_due_values(
+    cli: &BitwardenCliClient,
+    bitwarden_secret: &BitwardenSecret,
+    fetched: &mut HashMap<String, BitwardenItem>,
+    now: DateTime<Utc>,
+) -> Result<Vec<String>, BitwardenSecretError> {
+    let last_rotated = bitwarden_secret
+        .status
+        .as_ref()
+        .and_then(|x| x.last_rotated.clone())
+        .unwrap_or_default();
+
+    let mut rotated = vec![];
+    let mut changed = BTreeSet::<String>::new();
+    for entry in &bitwarden_secret.spec.content {
+        let (ContentSource::Field(reference), Some(policy)) =
+            (&entry.source, entry.generate_policy())
+        else {
+            continue;
+        };
+        if !is_rotation_due(entry, &last_rotated, now)? {
+            continue;
+        }
+        let Some(bitwarden_id) = get_bitwarden_id(entry, bitwarden_secret)? else {
+            continue;
+        };
+        let item = fetched
+            .get_mut(&bitwarden_id)
+            .ok_or_else(|| BitwardenSecretError::BitwardenItemNotFound(bitwarden_id.clone()))?;
+        let field = item
+            .fields
+            .iter_mut()
+            .flatten()
+            .find(|x| x.name == reference.name)
+            .ok_or_else(|| {
+                BitwardenSecretError::BitwardenFieldNotFound(
+                    bitwarden_id.clone(),
+                    reference.name.clone(),
+                )
+            })?;
+
+        let key = &entry.kubernetes_secret_key;
+        field.value = cli
+            .generate(&generator(key, &policy)?)
+            .await
+            .map_err(|e| BitwardenSecretError::GenerateFailed(key.clone(), e.to_string()))?;
+        rotated.push(key.clone());
+        changed.insert(bitwarden_id);
+    }
+
+    for bitwarden_id in changed {
+        let item = cli
+            .edit_item(&bitwarden_id, &fetched[&bitwarden_id])
+            .await
+            .map_err(|e| {
+                BitwardenSecretError::BitwardenWriteFailed(bitwarden_id.clone(), e.to_string())
+            })?;
+        info!("Bitwarden Item: {} rotated", bitwarden_id);
+        fetched.insert(bitwarden_id, item);
+    }
+    metrics::counter!("rotated_credentials_total").increment(rotated.len() as u64);
+    Ok(rotated)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bitwarden_cli::{BitwardenItem, BitwardenPasswordHistory};
+    use crate::operator::rotation::{
+        is_rotation_due, next_rotation, previous_field_value, record_rotations, validate_rotation,
+    };
+    use crate::operator::schemas::{
+        BitwardenFieldReference, BitwardenItemReference, BitwardenSecret, BitwardenSecretSpec,
+        ContentEntry, ContentSource, RotationPolicy,
+    };
+    use chrono::{Duration, TimeZone, Utc};
+    use std::collections::BTreeMap;
+
+    fn rotated(key: &str, interval: &str) -> ContentEntry {
+        ContentEntry {
+            kubernetes_secret_key: key.to_string(),
+            source: ContentSource::Field(BitwardenFieldReference {
+                bitwarden_id: None,
+                name: "password".to_string(),
+            }),
+            generate: None,
+            rotation: Some(RotationPolicy {
+                interval: interval.to_string(),
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn rotation_is_only_valid_on_fields() {
+        assert!(validate_rotation(&rotated("KEY", "720h")).is_ok());
+        assert!(validate_rotation(&rotated("KEY", "0")).is_err());
+        assert!(validate_rotation(&rotated("KEY", "soon")).is_err());
+        let note = ContentEntry {
+            source: ContentSource::Note(BitwardenItemReference::default()),
+            ..rotated("KEY", "720h")
+        };
+        assert!(validate_rotation(&note).is_err());
+    }
+
+    #[test]
+    fn rotations_are_scheduled_from_the_last_one() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let bitwarden_secret = BitwardenSecret::new(
+            "test",
+            BitwardenSecretSpec {
+                content: vec![rotated("DAILY", "24h"), rotated("HOURLY", "1h")],
+                ..Default::default()
+            },
+        );
+        let entry = &bitwarden_secret.spec.content[0];
+
+        // the first sync starts the schedule without rotating
+        let mut last_rotated = BTreeMap::from([("REMOVED".to_string(), now)]);
+        assert!(!is_rotation_due(entry, &last_rotated, now).unwrap());
+        record_rotations(&bitwarden_secret, &mut last_rotated, &[], now);
+        assert_eq!(last_rotated.keys().collect::<Vec<_>>(), ["DAILY", "HOURLY"]);
+        assert_eq!(
+            next_rotation(&bitwarden_secret, &last_rotated).unwrap(),
+            Some(now + Duration::try_hours(1).unwrap())
+        );
+
+        let later = now + Duration::try_hours(24).unwrap();
+        assert!(is_rotation_due(entry, &last_rotated, later).unwrap());
+        record_rotations(
+            &bitwarden_secret,
+            &mut last_rotated,
+            &["DAILY".to_string()],
+            later,
+        );
+        assert_eq!(last_rotated["DAILY"], later);
+        assert_eq!(last_rotated["HOURLY"], now);
+    }
+
+    #[test]
+    fn previous_value_comes_from_the_password_history() {
+        let history = |date: i64, password: &str| BitwardenPasswordHistory {
+            last_used_date: Utc.timestamp_opt(date, 0).single(),
+            password: password.to_string(),
+            ..Default::default()
+        };
+        let item = BitwardenItem {
+            password_history: Some(vec![
+                history(1, "password: first"),
+                history(3, "other: x"),
+                history(2, "password: sec: ond"),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            previous_field_value("password", &item).as_deref(),
+            Some("sec: ond")
+        );
+        assert_eq!(previous_field_value("missing", &item), None);
+    }
+}
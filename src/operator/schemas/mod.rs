@@ -0,0 +1,653 @@
+use chrono::{DateTime, Utc};
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Condition;
+use kube::core::crd::merge_crds;
+use kube::{CustomResource, CustomResourceExt};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use thiserror::Error;
+
+pub mod v1beta1;
+
+pub(crate) const GROUP: &str = "bitwarden-secret-operator.io";
+/// Version stored in etcd, older versions are converted to it by the conversion webhook
+pub(crate) const STORAGE_VERSION: &str = "v1";
+
+#[derive(CustomResource, Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
+#[kube(
+    group = "bitwarden-secret-operator.io",
+    version = "v1",
+    kind = "BitwardenSecret"
+)]
+#[kube(namespaced)]
+#[kube(status = "BitwardenSecretStatus")]
+#[kube(shortname = "bws", category = "bitwarden")]
+#[kube(
+    printcolumn = r#"{"name":"Ready","type":"string","jsonPath":".status.conditions[?(@.type==\"Ready\")].status"}"#,
+    printcolumn = r#"{"name":"Secret","type":"string","jsonPath":".status.target.name"}"#,
+    printcolumn = r#"{"name":"Last Sync","type":"date","jsonPath":".status.lastUpdated"}"#,
+    printcolumn = r#"{"name":"Age","type":"date","jsonPath":".metadata.creationTimestamp"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct BitwardenSecretSpec {
+    /// Name of the Kubernetes Secret, defaults to the same name of the CRD
+    #[serde(rename = "name")]
+    pub name: Option<String>,
+
+    /// Namespace where the Kubernetes Secret will be placed, defaults to the same namespace of the CRD
+    #[serde(rename = "namespace")]
+    pub namespace: Option<String>,
+
+    /// Type of secret to create, defaults to Opaque if not specified
+    #[serde(rename = "type")]
+    pub secret_type: Option<String>,
+
+    /// Name of the Bitwarden Secret, optional and can be overriden in `content.source`
+    #[serde(rename = "bitwardenId")]
+    pub bitwarden_id: Option<String>,
+
+    /// A set of labels to put to the secret resource
+    #[serde(rename = "labels")]
+    pub labels: Option<HashMap<String, String>>,
+
+    /// Content of secret
+    #[serde(rename = "content")]
+    #[schemars(schema_with = "content_schema")]
+    pub content: Vec<ContentEntry>,
+
+    /// A set of string data to put to the secret
+    #[serde(rename = "stringData")]
+    pub string_data: Option<HashMap<String, String>>,
+
+    /// How an existing secret is handled, `Owner` by default
+    #[serde(rename = "creationPolicy")]
+    pub creation_policy: Option<CreationPolicy>,
+
+    /// What happens to the secret when this resource is deleted, `Delete` by default
+    #[serde(rename = "deletionPolicy")]
+    pub deletion_policy: Option<DeletionPolicy>,
+
+    /// How often the secret is refreshed from Bitwarden (e.g. `1h`, `15m`), `0` only refreshes it on change
+    #[serde(rename = "refreshInterval")]
+    pub refresh_interval: Option<String>,
+
+    /// Cron expression at which the secret is refreshed from Bitwarden
+    #[serde(rename = "refreshSchedule")]
+    pub refresh_schedule: Option<String>,
+}
+
+/// Defines how the operator handles the target Secret, in particular when it already exists
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub enum CreationPolicy {
+    /// The Secret is owned by the BitwardenSecret and replaced if it already exists
+    #[default]
+    Owner,
+    /// Only the listed keys are managed, an existing Secret not owned by the operator is not adopted
+    Merge,
+    /// The Secret is written without owner reference and is not garbage collected
+    Orphan,
+    /// An existing Secret not owned by the BitwardenSecret is never adopted
+    None,
+}
+
+/// Defines what happens to the target Secret when the BitwardenSecret is deleted
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub enum DeletionPolicy {
+    /// The Secret is deleted if it is owned by the BitwardenSecret
+    #[default]
+    Delete,
+    /// The Secret is kept and released from the BitwardenSecret ownership
+    Retain,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct BitwardenSecretStatus {
+    /// Checksum of the rendered secret content
+    #[serde(rename = "checksum")]
+    pub checksum: String,
+    /// For operator internal refreshing rate
+    #[serde(rename = "lastUpdated")]
+    pub last_updated: Option<DateTime<Utc>>,
+    /// Reason why the secret couldn't be written according to `spec.creationPolicy`
+    #[serde(rename = "conflict", skip_serializing_if = "Option::is_none")]
+    pub conflict: Option<String>,
+    /// Secret currently written by the operator
+    #[serde(rename = "target", skip_serializing_if = "Option::is_none")]
+    pub target: Option<SecretTarget>,
+    /// Generation of the resource the status was computed for
+    #[serde(rename = "observedGeneration", skip_serializing_if = "Option::is_none")]
+    pub observed_generation: Option<i64>,
+    /// When the secret will next be refreshed from Bitwarden
+    #[serde(rename = "nextRefreshTime", skip_serializing_if = "Option::is_none")]
+    pub next_refresh_time: Option<DateTime<Utc>>,
+    /// Keys written to the secret
+    #[serde(rename = "syncedKeys", skip_serializing_if = "Option::is_none")]
+    pub synced_keys: Option<Vec<String>>,
+    /// Labels written to the Secret by the last sync
+    #[serde(rename = "syncedLabels", skip_serializing_if = "Option::is_none")]
+    pub synced_labels: Option<Vec<String>>,
+    /// resourceVersion of the Secret after the last sync
+    #[serde(
+        rename = "syncedResourceVersion",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub synced_resource_version: Option<String>,
+    /// Last rotation of each rotated Kubernetes Secret key
+    #[serde(rename = "lastRotated", skip_serializing_if = "Option::is_none")]
+    pub last_rotated: Option<BTreeMap<String, DateTime<Utc>>>,
+    /// Value of the force-sync annotation handled by the last sync
+    #[serde(rename = "forceSync", skip_serializing_if = "Option::is_none")]
+    pub force_sync: Option<String>,
+    /// Conditions `Ready`, `SourceAvailable`, `SecretSynced` and `Paused`
+    #[serde(rename = "conditions", skip_serializing_if = "Option::is_none")]
+    pub conditions: Option<Vec<Condition>>,
+}
+
+/// Reference to the Secret written by the operator
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub struct SecretTarget {
+    #[serde(rename = "name")]
+    pub name: String,
+    #[serde(rename = "namespace")]
+    pub namespace: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ContentEntry {
+    /// Name of the Kubernetes Secret key
+    #[serde(rename = "kubernetesSecretKey")]
+    #[schemars(schema_with = "secret_key_schema")]
+    pub kubernetes_secret_key: String,
+    /// Where the value of the key comes from, exactly one of `value`, `note` or `field`
+    #[serde(rename = "source")]
+    #[schemars(schema_with = "content_source_schema")]
+    pub source: ContentSource,
+    /// Generates the value and stores it in Bitwarden when the note, the field or the item is
+    /// missing
+    #[serde(rename = "generate", skip_serializing_if = "Option::is_none")]
+    pub generate: Option<GeneratePolicy>,
+    /// Periodically replaces the value of the field with a generated one
+    #[serde(rename = "rotation", skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<RotationPolicy>,
+}
+
+impl ContentEntry {
+    /// Rules of the generated values, set when the entry is generated or rotated
+    pub fn generate_policy(&self) -> Option<GeneratePolicy> {
+        let rotation = self.rotation.as_ref();
+        rotation
+            .and_then(|x| x.generate.clone())
+            .or_else(|| self.generate.clone())
+            .or_else(|| rotation.map(|_| GeneratePolicy::default()))
+    }
+}
+
+/// Scheduled rotation of a field, the previous value stays in the item `passwordHistory`
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, JsonSchema)]
+pub struct RotationPolicy {
+    /// Time between two rotations, such as `720h`
+    #[serde(rename = "interval")]
+    pub interval: String,
+    /// Rules of the rotated values, `generate` of the entry by default
+    #[serde(rename = "generate", skip_serializing_if = "Option::is_none")]
+    pub generate: Option<GeneratePolicy>,
+    /// Kubernetes Secret key exposing the previous value, for dual-credential rollovers
+    #[serde(rename = "previousKey", skip_serializing_if = "Option::is_none")]
+    pub previous_key: Option<String>,
+}
+
+/// Rules of a generated credential
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, JsonSchema)]
+pub struct GeneratePolicy {
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub generator: Option<GeneratorType>,
+    /// Characters of a password, 32 by default, or words of a passphrase, 5 by default
+    #[serde(rename = "length", skip_serializing_if = "Option::is_none")]
+    pub length: Option<u8>,
+    /// Characters of a password, `Uppercase`, `Lowercase` and `Numbers` by default
+    #[serde(rename = "charset", skip_serializing_if = "Option::is_none")]
+    pub charset: Option<Vec<Charset>>,
+    /// Separator of the passphrase words, `-` by default
+    #[serde(rename = "separator", skip_serializing_if = "Option::is_none")]
+    pub separator: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub enum GeneratorType {
+    #[default]
+    Password,
+    Passphrase,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+pub enum Charset {
+    Uppercase,
+    Lowercase,
+    Numbers,
+    Special,
+}
+
+/// Where the value of a Secret key comes from, exactly one of them has to be set
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum ContentSource {
+    /// Literal value, Bitwarden is not queried
+    Value(String),
+    /// Notes of the Bitwarden item
+    Note(BitwardenItemReference),
+    /// Custom field of the Bitwarden item
+    Field(BitwardenFieldReference),
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, JsonSchema)]
+pub struct BitwardenItemReference {
+    /// Overrides `spec.bitwardenId`
+    #[serde(rename = "bitwardenId", skip_serializing_if = "Option::is_none")]
+    pub bitwarden_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, JsonSchema)]
+pub struct BitwardenFieldReference {
+    /// Overrides `spec.bitwardenId`
+    #[serde(rename = "bitwardenId", skip_serializing_if = "Option::is_none")]
+    pub bitwarden_id: Option<String>,
+    /// Name of the Bitwarden `field` to use
+    #[serde(rename = "name")]
+    #[schemars(schema_with = "field_name_schema")]
+    pub name: String,
+}
+
+/// Adds `x-kubernetes-validations` CEL rules, as `(rule, message)`, to the schema of `T`
+fn with_validations<T: JsonSchema>(gen: &mut SchemaGenerator, rules: &[(&str, &str)]) -> Schema {
+    let mut schema = T::json_schema(gen).into_object();
+    let rules = rules
+        .iter()
+        .map(|(rule, message)| json!({ "rule": rule, "message": message }))
+        .collect();
+    schema
+        .extensions
+        .insert("x-kubernetes-validations".to_string(), rules);
+    Schema::Object(schema)
+}
+
+fn content_schema(gen: &mut SchemaGenerator) -> Schema {
+    with_validations::<Vec<ContentEntry>>(gen, &[("size(self) > 0", "content must not be empty")])
+}
+
+fn secret_key_schema(gen: &mut SchemaGenerator) -> Schema {
+    let mut schema = with_validations::<String>(
+        gen,
+        &[(
+            "self.matches('^[-._a-zA-Z0-9]+$')",
+            "kubernetesSecretKey must match [-._a-zA-Z0-9]+",
+        )],
+    )
+    .into_object();
+    // bounds the cost of the rule
+    schema.string().max_length = Some(253);
+    Schema::Object(schema)
+}
+
+fn content_source_schema(gen: &mut SchemaGenerator) -> Schema {
+    with_validations::<ContentSource>(
+        gen,
+        &[(
+            "(has(self.value) ? 1 : 0) + (has(self.note) ? 1 : 0) + (has(self.field) ? 1 : 0) == 1",
+            "exactly one of value, note or field must be set",
+        )],
+    )
+}
+
+fn field_name_schema(gen: &mut SchemaGenerator) -> Schema {
+    with_validations::<String>(gen, &[("size(self) > 0", "field name must not be empty")])
+}
+
+/// Writes keys of a Kubernetes Secret into a Bitwarden item
+#[derive(CustomResource, Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
+#[kube(
+    group = "bitwarden-secret-operator.io",
+    version = "v1",
+    kind = "BitwardenPushSecret"
+)]
+#[kube(namespaced)]
+#[kube(status = "BitwardenPushSecretStatus")]
+#[kube(shortname = "bwps", category = "bitwarden")]
+#[kube(
+    printcolumn = r#"{"name":"Ready","type":"string","jsonPath":".status.conditions[?(@.type==\"Ready\")].status"}"#,
+    printcolumn = r#"{"name":"Item","type":"string","jsonPath":".status.bitwardenId"}"#,
+    printcolumn = r#"{"name":"Last Push","type":"date","jsonPath":".status.lastPushed"}"#,
+    printcolumn = r#"{"name":"Age","type":"date","jsonPath":".metadata.creationTimestamp"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct BitwardenPushSecretSpec {
+    /// Secret in the same namespace to read the keys from
+    #[serde(rename = "secretName")]
+    pub secret_name: String,
+
+    /// Item to write into, a new item is created when it is not set
+    #[serde(rename = "bitwardenId")]
+    pub bitwarden_id: Option<String>,
+
+    /// Name of the created item, `<namespace>/<name>` by default
+    #[serde(rename = "itemName")]
+    pub item_name: Option<String>,
+
+    #[serde(rename = "data")]
+    #[schemars(schema_with = "push_data_schema")]
+    pub data: Vec<PushEntry>,
+
+    #[serde(rename = "conflictPolicy")]
+    pub conflict_policy: Option<PushConflictPolicy>,
+
+    #[serde(rename = "refreshInterval")]
+    pub refresh_interval: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+pub struct PushEntry {
+    #[serde(rename = "secretKey")]
+    pub secret_key: String,
+    #[serde(rename = "target")]
+    #[schemars(schema_with = "push_target_schema")]
+    pub target: PushTarget,
+}
+
+/// Where the value of a Secret key is written in the Bitwarden item, exactly one of them has to be
+/// set
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum PushTarget {
+    /// Custom field of the item, created as a hidden field when missing
+    Field(PushFieldTarget),
+    /// Notes of the item
+    Note(PushNoteTarget),
+    /// Login property of the item
+    Login(PushLoginTarget),
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, JsonSchema)]
+pub struct PushFieldTarget {
+    #[serde(rename = "name")]
+    #[schemars(schema_with = "field_name_schema")]
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, JsonSchema)]
+pub struct PushNoteTarget {}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, JsonSchema)]
+pub struct PushLoginTarget {
+    #[serde(rename = "property")]
+    pub property: LoginProperty,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub enum LoginProperty {
+    #[default]
+    Username,
+    Password,
+    Totp,
+}
+
+/// Defines what happens when the Bitwarden item was modified since the operator last wrote it
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub enum PushConflictPolicy {
+    /// The item is left untouched and the conflict is reported in the status
+    #[default]
+    Fail,
+    /// The item is overwritten
+    Overwrite,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct BitwardenPushSecretStatus {
+    /// Item written by the operator
+    #[serde(rename = "bitwardenId", skip_serializing_if = "Option::is_none")]
+    pub bitwarden_id: Option<String>,
+    /// `revisionDate` of the item after the last write, used to detect concurrent modifications
+    #[serde(rename = "revisionDate", skip_serializing_if = "Option::is_none")]
+    pub revision_date: Option<DateTime<Utc>>,
+    #[serde(rename = "lastPushed", skip_serializing_if = "Option::is_none")]
+    pub last_pushed: Option<DateTime<Utc>>,
+    #[serde(rename = "conflict", skip_serializing_if = "Option::is_none")]
+    pub conflict: Option<String>,
+    #[serde(rename = "observedGeneration", skip_serializing_if = "Option::is_none")]
+    pub observed_generation: Option<i64>,
+    #[serde(rename = "pushedKeys", skip_serializing_if = "Option::is_none")]
+    pub pushed_keys: Option<Vec<String>>,
+    /// Condition `Ready`
+    #[serde(rename = "conditions", skip_serializing_if = "Option::is_none")]
+    pub conditions: Option<Vec<Condition>>,
+}
+
+fn push_data_schema(gen: &mut SchemaGenerator) -> Schema {
+    with_validations::<Vec<PushEntry>>(gen, &[("size(self) > 0", "data must not be empty")])
+}
+
+fn push_target_schema(gen: &mut SchemaGenerator) -> Schema {
+    with_validations::<PushTarget>(
+        gen,
+        &[(
+            "(has(self.field) ? 1 : 0) + (has(self.note) ? 1 : 0) + (has(self.login) ? 1 : 0) == 1",
+            "exactly one of field, note or login must be set",
+        )],
+    )
+}
+
+#[derive(Error, Debug)]
+pub enum BitwardenSecretError {
+    #[error("The given Kubernetes secret key seems misconfigured {0}")]
+    MissingBitwardenId(String),
+
+    #[error("Bitwarden Item: {0} not found")]
+    BitwardenItemNotFound(String),
+
+    #[error("Bitwarden Item: {0}, field: {1} not found")]
+    BitwardenFieldNotFound(String, String),
+
+    #[error("Bitwarden Item: {0}, error on field: {1}")]
+    WrongValues(String, String),
+
+    #[error("Kubernetes secret key: {0} is invalid, it must match [-._a-zA-Z0-9]+")]
+    InvalidKey(String),
+
+    #[error("Kubernetes secret key: {0} is defined more than once")]
+    DuplicateKey(String),
+
+    #[error("Secret: {0} conflicts with creation policy: {1}")]
+    SecretConflict(String, String),
+
+    #[error("Invalid refresh configuration: {0}")]
+    InvalidRefresh(String),
+
+    #[error("Bitwarden Item: {0} couldn't be fetched, vault unavailable: {1}")]
+    BitwardenUnavailable(String, String),
+
+    #[error("Secret: {0} not found")]
+    SecretNotFound(String),
+
+    #[error("Secret: {0}, key: {1} not found")]
+    SecretKeyNotFound(String, String),
+
+    #[error("Secret: {0}, key: {1} is not valid UTF-8")]
+    InvalidSecretValue(String, String),
+
+    #[error("Bitwarden Item: {0} conflicts with the last push: {1}")]
+    ItemConflict(String, String),
+
+    #[error("Bitwarden Item: {0} couldn't be written: {1}")]
+    BitwardenWriteFailed(String, String),
+
+    #[error("Kubernetes secret key: {0} has an invalid generate policy: {1}")]
+    InvalidGeneratePolicy(String, String),
+
+    #[error("Kubernetes secret key: {0} couldn't be generated: {1}")]
+    GenerateFailed(String, String),
+
+    #[error("Kubernetes secret key: {0} has an invalid rotation policy: {1}")]
+    InvalidRotationPolicy(String, String),
+
+    #[error("Namespace: {0} is not watched by the operator")]
+    NamespaceOutOfScope(String),
+
+    #[error("Bitwarden vault couldn't be synced: {0}")]
+    VaultSyncFailed(String),
+}
+
+impl BitwardenSecretError {
+    /// Machine readable reason, used in status conditions
+    pub fn reason(&self) -> &'static str {
+        match self {
+            BitwardenSecretError::MissingBitwardenId(_) => "MissingBitwardenId",
+            BitwardenSecretError::BitwardenItemNotFound(_) => "ItemNotFound",
+            BitwardenSecretError::BitwardenFieldNotFound(_, _) => "FieldNotFound",
+            BitwardenSecretError::WrongValues(_, _) => "InvalidField",
+            BitwardenSecretError::InvalidKey(_) => "InvalidKey",
+            BitwardenSecretError::DuplicateKey(_) => "DuplicateKey",
+            BitwardenSecretError::SecretConflict(_, _) => "Conflict",
+            BitwardenSecretError::InvalidRefresh(_) => "InvalidRefresh",
+            BitwardenSecretError::BitwardenUnavailable(_, _) => "VaultUnavailable",
+            BitwardenSecretError::SecretNotFound(_) => "SecretNotFound",
+            BitwardenSecretError::SecretKeyNotFound(_, _) => "KeyNotFound",
+            BitwardenSecretError::InvalidSecretValue(_, _) => "InvalidValue",
+            BitwardenSecretError::ItemConflict(_, _) => "ItemConflict",
+            BitwardenSecretError::BitwardenWriteFailed(_, _) => "WriteFailed",
+            BitwardenSecretError::InvalidGeneratePolicy(_, _) => "InvalidGeneratePolicy",
+            BitwardenSecretError::GenerateFailed(_, _) => "GenerateFailed",
+            BitwardenSecretError::InvalidRotationPolicy(_, _) => "InvalidRotationPolicy",
+            BitwardenSecretError::NamespaceOutOfScope(_) => "NamespaceOutOfScope",
+            BitwardenSecretError::VaultSyncFailed(_) => "VaultUnavailable",
+        }
+    }
+
+    /// Returns true when the error comes from Bitwarden rather than from the spec
+    pub fn is_source_error(&self) -> bool {
+        matches!(
+            self,
+            BitwardenSecretError::BitwardenItemNotFound(_)
+                | BitwardenSecretError::BitwardenFieldNotFound(_, _)
+                | BitwardenSecretError::WrongValues(_, _)
+                | BitwardenSecretError::BitwardenUnavailable(_, _)
+                | BitwardenSecretError::BitwardenWriteFailed(_, _)
+                | BitwardenSecretError::GenerateFailed(_, _)
+                | BitwardenSecretError::VaultSyncFailed(_)
+        )
+    }
+}
+
+/// CRD of every version of the BitwardenSecret. Only the stored version is served, the operator
+/// serves the others once it has pointed the conversion webhook to its own Service.
+pub fn bitwarden_secret_crd() -> CustomResourceDefinition {
+    let mut crd = merge_crds(
+        vec![v1beta1::BitwardenSecret::crd(), BitwardenSecret::crd()],
+        STORAGE_VERSION,
+    )
+    .expect("every version shares the same group and names");
+    for version in &mut crd.spec.versions {
+        if version.name != STORAGE_VERSION {
+            version.served = false;
+            version.deprecated = Some(true);
+            version.deprecation_warning = Some(format!(
+                "{GROUP}/{} BitwardenSecret is deprecated, use {GROUP}/{STORAGE_VERSION}",
+                version.name
+            ));
+        }
+    }
+    crd
+}
+
+/// Converts a BitwardenSecret of any served version to `desired_api_version`
+pub fn convert_bitwarden_secret(
+    object: serde_json::Value,
+    desired_api_version: &str,
+) -> eyre::Result<serde_json::Value> {
+    let api_version = object
+        .get("apiVersion")
+        .and_then(|x| x.as_str())
+        .unwrap_or_default()
+        .to_string();
+    let converted: BitwardenSecret = match api_version.strip_prefix(&format!("{GROUP}/")) {
+        Some("v1beta1") => serde_json::from_value::<v1beta1::BitwardenSecret>(object)?.into(),
+        Some(STORAGE_VERSION) => serde_json::from_value(object)?,
+        _ => eyre::bail!("unsupported apiVersion {api_version}"),
+    };
+
+    Ok(
+        match desired_api_version.strip_prefix(&format!("{GROUP}/")) {
+            Some("v1beta1") => serde_json::to_value(v1beta1::BitwardenSecret::from(converted))?,
+            Some(STORAGE_VERSION) => serde_json::to_value(converted)?,
+            _ => eyre::bail!("unsupported apiVersion {desired_api_version}"),
+        },
+    )
+}
+
+pub(crate) const OPERATOR_MANAGED_BY_LABEL: &str = "app.kubernetes.io/managed-by";
+pub(crate) const OPERATOR_MANAGED_BY: &str = "bitwarden-secret-operator-rs";
+pub(crate) const OPERATOR_FINALIZER: &str = "bitwarden-secret-operator.io/cleanup";
+pub(crate) const OPERATOR_HASH_ANNOTATION: &str = "bitwarden-secret-operator.io/hash";
+/// uid of the BitwardenSecret controlling a Secret of another namespace, which can't hold an owner
+/// reference to it
+pub(crate) const OWNER_UID_ANNOTATION: &str = "bitwarden-secret-operator.io/owner-uid";
+/// `namespace/name` of the BitwardenSecret controlling a Secret of another namespace, maps the
+/// changes of the Secret to its owner
+pub(crate) const OWNER_ANNOTATION: &str = "bitwarden-secret-operator.io/owner";
+/// Any new value syncs the vault and the Secret right away
+pub(crate) const FORCE_SYNC_ANNOTATION: &str = "bitwarden-secret-operator.io/force-sync";
+/// Set on the Secrets pushed by a BitwardenPushSecret, only the labelled Secrets are watched
+pub(crate) const PUSHED_LABEL: &str = "bitwarden-secret-operator.io/pushed";
+/// `true` suspends the reconciliation of the resource
+pub(crate) const PAUSED_ANNOTATION: &str = "bitwarden-secret-operator.io/paused";
+
+#[cfg(test)]
+mod tests {
+    use crate::operator::schemas::{bitwarden_secret_crd, BitwardenPushSecret, STORAGE_VERSION};
+    use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+    use kube::CustomResourceExt;
+
+    #[test]
+    fn crd_serves_every_version_with_validations() {
+        let crd = bitwarden_secret_crd();
+        assert_eq!(crd.spec.names.short_names, Some(vec!["bws".to_string()]));
+        assert_eq!(crd.spec.versions.len(), 2);
+        // the conversion webhook is configured by the operator
+        assert!(crd.spec.conversion.is_none());
+        for version in &crd.spec.versions {
+            assert_eq!(version.storage, version.name == STORAGE_VERSION);
+            assert_eq!(version.served, version.storage);
+            assert_eq!(
+                version.additional_printer_columns.as_ref().unwrap().len(),
+                4
+            );
+        }
+
+        let storage = &crd.spec.versions[0];
+        let schema = serde_json::to_value(&storage.schema).unwrap();
+        let content = &schema["openAPIV3Schema"]["properties"]["spec"]["properties"]["content"];
+        assert!(content["x-kubernetes-validations"].is_array());
+        let entry = &content["items"]["properties"];
+        assert!(entry["kubernetesSecretKey"]["x-kubernetes-validations"].is_array());
+        assert!(entry["source"]["x-kubernetes-validations"].is_array());
+    }
+
+    #[test]
+    fn chart_crds_are_generated() {
+        let parse = |x: &str| serde_yaml::from_str::<CustomResourceDefinition>(x).unwrap();
+        assert_eq!(
+            parse(include_str!(
+                "../../../charts/bitwarden-secret-operator/crds/bitwarden-secret.yaml"
+            )),
+            bitwarden_secret_crd()
+        );
+        assert_eq!(
+            parse(include_str!(
+                "../../../charts/bitwarden-secret-operator/crds/bitwarden-push-secret.yaml"
+            )),
+            BitwardenPushSecret::crd()
+        );
+    }
+}
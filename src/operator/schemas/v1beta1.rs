@@ -0,0 +1,489 @@
+//! Previous version of the BitwardenSecret, still served through the conversion webhook so
+//! existing manifests keep working
+
+use crate::operator::schemas::{
+    self, BitwardenFieldReference, BitwardenItemReference, BitwardenSecretStatus, ContentSource,
+    CreationPolicy, DeletionPolicy, GeneratePolicy, RotationPolicy,
+};
+use kube::api::ObjectMeta;
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Fields of the content entries which only exist in v1, kept as JSON by key of the entry so
+/// converting to v1beta1 and back is lossless
+const V1_FIELDS_ANNOTATION: &str = "bitwarden-secret-operator.io/v1-fields";
+/// Source fields of the content entries which v1 doesn't keep, e.g. the item id of a literal value,
+/// kept as JSON by key of the entry so converting to v1 and back is lossless
+const V1BETA1_FIELDS_ANNOTATION: &str = "bitwarden-secret-operator.io/v1beta1-fields";
+
+#[derive(CustomResource, Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
+#[kube(
+    group = "bitwarden-secret-operator.io",
+    version = "v1beta1",
+    kind = "BitwardenSecret"
+)]
+#[kube(namespaced)]
+#[kube(status = "BitwardenSecretStatus")]
+#[kube(shortname = "bws", category = "bitwarden")]
+#[kube(
+    printcolumn = r#"{"name":"Ready","type":"string","jsonPath":".status.conditions[?(@.type==\"Ready\")].status"}"#,
+    printcolumn = r#"{"name":"Secret","type":"string","jsonPath":".status.target.name"}"#,
+    printcolumn = r#"{"name":"Last Sync","type":"date","jsonPath":".status.lastUpdated"}"#,
+    printcolumn = r#"{"name":"Age","type":"date","jsonPath":".metadata.creationTimestamp"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct BitwardenSecretSpec {
+    /// Name of the Kubernetes Secret, defaults to the same name of the CRD
+    #[serde(rename = "name")]
+    pub name: Option<String>,
+
+    /// Namespace where the Kubernetes Secret will be placed, defaults to the same namespace of the CRD
+    #[serde(rename = "namespace")]
+    pub namespace: Option<String>,
+
+    /// Type of secret to create, defaults to Opaque if not specified
+    #[serde(rename = "type")]
+    pub secret_type: Option<String>,
+
+    /// Name of the Bitwarden Secret, optional and can be overriden by fields in `content.bitwardenId`
+    #[serde(rename = "bitwardenId")]
+    pub bitwarden_id: Option<String>,
+
+    /// A set of labels to put to the secret resource
+    #[serde(rename = "labels")]
+    pub labels: Option<HashMap<String, String>>,
+
+    /// Content of secret
+    #[serde(rename = "content")]
+    pub content: Vec<ContentEntry>,
+
+    /// A set of string data to put to the secret
+    #[serde(rename = "stringData")]
+    pub string_data: Option<HashMap<String, String>>,
+
+    /// How an existing secret is handled, `Owner` by default
+    #[serde(rename = "creationPolicy")]
+    pub creation_policy: Option<CreationPolicy>,
+
+    /// What happens to the secret when this resource is deleted, `Delete` by default
+    #[serde(rename = "deletionPolicy")]
+    pub deletion_policy: Option<DeletionPolicy>,
+
+    /// How often the secret is refreshed from Bitwarden (e.g. `1h`, `15m`), `0` only refreshes it on change
+    #[serde(rename = "refreshInterval")]
+    pub refresh_interval: Option<String>,
+
+    /// Cron expression at which the secret is refreshed from Bitwarden
+    #[serde(rename = "refreshSchedule")]
+    pub refresh_schedule: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ContentEntry {
+    /// Name of the Bitwarden `id` field
+    #[serde(rename = "bitwardenId")]
+    pub bitwarden_id: Option<String>,
+    /// Name of the Bitwarden `field` to use
+    #[serde(rename = "bitwardenSecretField")]
+    pub bitwarden_secret_field: Option<String>,
+    /// Tells whether or not to use `note` instead of `fields`
+    #[serde(rename = "bitwardenUseNote")]
+    pub bitwarden_use_note: Option<bool>,
+    /// Name of the Kubernetes Secret key
+    #[serde(rename = "kubernetesSecretKey")]
+    pub kubernetes_secret_key: String,
+    /// Name of the Kubernetes Secret Value
+    #[serde(rename = "kubernetesSecretValue")]
+    pub kubernetes_secret_value: Option<String>,
+}
+
+impl From<ContentEntry> for schemas::ContentEntry {
+    fn from(entry: ContentEntry) -> Self {
+        // kubernetesSecretValue is prioritized over bitwardenUseNote, itself prioritized over
+        // bitwardenSecretField
+        let bitwarden_id = entry.bitwarden_id;
+        let source = match (entry.kubernetes_secret_value, entry.bitwarden_use_note) {
+            (Some(value), _) => ContentSource::Value(value),
+            (None, Some(true)) => ContentSource::Note(BitwardenItemReference { bitwarden_id }),
+            // an entry without any source is kept readable, the empty field name is rejected
+            // when the BitwardenSecret is validated
+            (None, _) => ContentSource::Field(BitwardenFieldReference {
+                bitwarden_id,
+                name: entry.bitwarden_secret_field.unwrap_or_default(),
+            }),
+        };
+        schemas::ContentEntry {
+            kubernetes_secret_key: entry.kubernetes_secret_key,
+            source,
+            generate: None,
+            rotation: None,
+        }
+    }
+}
+
+impl From<schemas::ContentEntry> for ContentEntry {
+    fn from(entry: schemas::ContentEntry) -> Self {
+        let mut converted = ContentEntry {
+            kubernetes_secret_key: entry.kubernetes_secret_key,
+            ..Default::default()
+        };
+        match entry.source {
+            ContentSource::Value(value) => converted.kubernetes_secret_value = Some(value),
+            ContentSource::Note(reference) => {
+                converted.bitwarden_id = reference.bitwarden_id;
+                converted.bitwarden_use_note = Some(true);
+            }
+            ContentSource::Field(reference) => {
+                converted.bitwarden_id = reference.bitwarden_id;
+                converted.bitwarden_secret_field = Some(reference.name);
+            }
+        }
+        converted
+    }
+}
+
+/// Fields of a content entry which v1beta1 can't hold
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+struct V1Fields {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generate: Option<GeneratePolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rotation: Option<RotationPolicy>,
+}
+
+impl V1Fields {
+    fn from_entry(entry: &schemas::ContentEntry) -> Option<Self> {
+        let fields = V1Fields {
+            generate: entry.generate.clone(),
+            rotation: entry.rotation.clone(),
+        };
+        (fields != V1Fields::default()).then_some(fields)
+    }
+
+    fn restore(self, entry: &mut schemas::ContentEntry) {
+        entry.generate = self.generate;
+        entry.rotation = self.rotation;
+    }
+}
+
+/// Source fields of a content entry which its v1 source can't hold
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct V1beta1Fields {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bitwarden_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bitwarden_secret_field: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bitwarden_use_note: Option<bool>,
+}
+
+impl V1beta1Fields {
+    fn from_entry(entry: &ContentEntry) -> Option<Self> {
+        let converted = ContentEntry::from(schemas::ContentEntry::from(entry.clone()));
+        (converted != *entry).then(|| V1beta1Fields {
+            bitwarden_id: entry.bitwarden_id.clone(),
+            bitwarden_secret_field: entry.bitwarden_secret_field.clone(),
+            bitwarden_use_note: entry.bitwarden_use_note,
+        })
+    }
+
+    /// Restores the fields, unless the source has been changed in v1 since
+    fn restore(self, entry: &mut ContentEntry) {
+        let restored = ContentEntry {
+            bitwarden_id: self.bitwarden_id,
+            bitwarden_secret_field: self.bitwarden_secret_field,
+            bitwarden_use_note: self.bitwarden_use_note,
+            ..entry.clone()
+        };
+        let source = |x: &ContentEntry| schemas::ContentEntry::from(x.clone()).source;
+        if source(&restored) == source(entry) {
+            *entry = restored;
+        }
+    }
+}
+
+impl From<BitwardenSecret> for schemas::BitwardenSecret {
+    fn from(bitwarden_secret: BitwardenSecret) -> Self {
+        let mut metadata = bitwarden_secret.metadata;
+        let v1beta1_fields = bitwarden_secret
+            .spec
+            .content
+            .iter()
+            .filter_map(|x| {
+                Some((
+                    x.kubernetes_secret_key.clone(),
+                    V1beta1Fields::from_entry(x)?,
+                ))
+            })
+            .collect::<BTreeMap<_, _>>();
+        let mut spec: schemas::BitwardenSecretSpec = bitwarden_secret.spec.into();
+        let mut fields = take_fields::<V1Fields>(&mut metadata, V1_FIELDS_ANNOTATION);
+        for entry in &mut spec.content {
+            if let Some(x) = fields.remove(&entry.kubernetes_secret_key) {
+                x.restore(entry);
+            }
+        }
+        take_fields::<V1beta1Fields>(&mut metadata, V1BETA1_FIELDS_ANNOTATION);
+        put_fields(&mut metadata, V1BETA1_FIELDS_ANNOTATION, &v1beta1_fields);
+        schemas::BitwardenSecret {
+            metadata,
+            spec,
+            status: bitwarden_secret.status,
+        }
+    }
+}
+
+impl From<schemas::BitwardenSecret> for BitwardenSecret {
+    fn from(bitwarden_secret: schemas::BitwardenSecret) -> Self {
+        let mut metadata = bitwarden_secret.metadata;
+        let fields = bitwarden_secret
+            .spec
+            .content
+            .iter()
+            .filter_map(|x| Some((x.kubernetes_secret_key.clone(), V1Fields::from_entry(x)?)))
+            .collect::<BTreeMap<_, _>>();
+        take_fields::<V1Fields>(&mut metadata, V1_FIELDS_ANNOTATION);
+        put_fields(&mut metadata, V1_FIELDS_ANNOTATION, &fields);
+        let mut spec: BitwardenSecretSpec = bitwarden_secret.spec.into();
+        let mut v1beta1_fields =
+            take_fields::<V1beta1Fields>(&mut metadata, V1BETA1_FIELDS_ANNOTATION);
+        for entry in &mut spec.content {
+            if let Some(x) = v1beta1_fields.remove(&entry.kubernetes_secret_key) {
+                x.restore(entry);
+            }
+        }
+        BitwardenSecret {
+            metadata,
+            spec,
+            status: bitwarden_secret.status,
+        }
+    }
+}
+
+/// Removes an annotation of fields kept by key of the content entry, an invalid one is dropped
+fn take_fields<T: DeserializeOwned>(
+    metadata: &mut ObjectMeta,
+    annotation: &str,
+) -> BTreeMap<String, T> {
+    let Some(annotations) = metadata.annotations.as_mut() else {
+        return BTreeMap::new();
+    };
+    let fields = annotations.remove(annotation);
+    if annotations.is_empty() {
+        metadata.annotations = None;
+    }
+    fields
+        .and_then(|x| serde_json::from_str(&x).ok())
+        .unwrap_or_default()
+}
+
+/// Sets an annotation of fields kept by key of the content entry, unless there are none
+fn put_fields<T: Serialize>(
+    metadata: &mut ObjectMeta,
+    annotation: &str,
+    fields: &BTreeMap<String, T>,
+) {
+    if fields.is_empty() {
+        return;
+    }
+    metadata
+        .annotations
+        .get_or_insert_with(Default::default)
+        .insert(
+            annotation.to_string(),
+            serde_json::to_string(fields).unwrap_or_default(),
+        );
+}
+
+impl From<BitwardenSecretSpec> for schemas::BitwardenSecretSpec {
+    fn from(spec: BitwardenSecretSpec) -> Self {
+        schemas::BitwardenSecretSpec {
+            name: spec.name,
+            namespace: spec.namespace,
+            secret_type: spec.secret_type,
+            bitwarden_id: spec.bitwarden_id,
+            labels: spec.labels,
+            content: spec.content.into_iter().map(Into::into).collect(),
+            string_data: spec.string_data,
+            creation_policy: spec.creation_policy,
+            deletion_policy: spec.deletion_policy,
+            refresh_interval: spec.refresh_interval,
+            refresh_schedule: spec.refresh_schedule,
+        }
+    }
+}
+
+impl From<schemas::BitwardenSecretSpec> for BitwardenSecretSpec {
+    fn from(spec: schemas::BitwardenSecretSpec) -> Self {
+        BitwardenSecretSpec {
+            name: spec.name,
+            namespace: spec.namespace,
+            secret_type: spec.secret_type,
+            bitwarden_id: spec.bitwarden_id,
+            labels: spec.labels,
+            content: spec.content.into_iter().map(Into::into).collect(),
+            string_data: spec.string_data,
+            creation_policy: spec.creation_policy,
+            deletion_policy: spec.deletion_policy,
+            refresh_interval: spec.refresh_interval,
+            refresh_schedule: spec.refresh_schedule,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::operator::schemas::v1beta1::ContentEntry;
+    use crate::operator::schemas::{
+        self, convert_bitwarden_secret, BitwardenFieldReference, ContentSource,
+    };
+    use serde_json::json;
+
+    fn entry(use_note: Option<bool>, field: Option<&str>, value: Option<&str>) -> ContentEntry {
+        ContentEntry {
+            bitwarden_id: Some("00000000-0000-0000-0000-000000000000".to_string()),
+            bitwarden_secret_field: field.map(str::to_string),
+            bitwarden_use_note: use_note,
+            kubernetes_secret_key: "KEY".to_string(),
+            kubernetes_secret_value: value.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn content_entries_round_trip() {
+        // literal values are not read from Bitwarden, they don't keep the item id
+        let literal = ContentEntry {
+            bitwarden_id: None,
+            ..entry(None, None, Some("value"))
+        };
+        for entry in [
+            literal,
+            entry(Some(true), None, None),
+            entry(None, Some("password"), None),
+        ] {
+            let converted: schemas::ContentEntry = entry.clone().into();
+            assert_eq!(ContentEntry::from(converted), entry);
+        }
+    }
+
+    #[test]
+    fn content_entries_keep_the_prioritized_source() {
+        let converted: schemas::ContentEntry = entry(Some(true), Some("x"), Some("value")).into();
+        assert_eq!(converted.source, ContentSource::Value("value".to_string()));
+
+        let converted: schemas::ContentEntry = entry(Some(false), None, None).into();
+        assert_eq!(
+            converted.source,
+            ContentSource::Field(BitwardenFieldReference {
+                bitwarden_id: Some("00000000-0000-0000-0000-000000000000".to_string()),
+                name: "".to_string(),
+            })
+        );
+    }
+
+    /// The optional fields are serialized as nulls
+    fn strip_nulls(value: serde_json::Value) -> serde_json::Value {
+        match value {
+            serde_json::Value::Object(map) => map
+                .into_iter()
+                .filter(|(_, x)| !x.is_null())
+                .map(|(k, x)| (k, strip_nulls(x)))
+                .collect(),
+            serde_json::Value::Array(items) => items.into_iter().map(strip_nulls).collect(),
+            x => x,
+        }
+    }
+
+    #[test]
+    fn objects_are_converted_between_versions() {
+        let v1beta1 = json!({
+            "apiVersion": "bitwarden-secret-operator.io/v1beta1",
+            "kind": "BitwardenSecret",
+            "metadata": { "name": "test", "namespace": "default", "resourceVersion": "42" },
+            "spec": {
+                "bitwardenId": "00000000-0000-0000-0000-000000000000",
+                "content": [
+                    { "kubernetesSecretKey": "NOTE", "bitwardenUseNote": true },
+                    { "kubernetesSecretKey": "PASSWORD", "bitwardenSecretField": "password" },
+                    // fields the v1 source doesn't keep
+                    {
+                        "kubernetesSecretKey": "LITERAL",
+                        "kubernetesSecretValue": "value",
+                        "bitwardenId": "11111111-1111-1111-1111-111111111111"
+                    },
+                    {
+                        "kubernetesSecretKey": "NOTE_WITH_FIELD",
+                        "bitwardenUseNote": true,
+                        "bitwardenSecretField": "ignored"
+                    },
+                    {
+                        "kubernetesSecretKey": "TOKEN",
+                        "bitwardenUseNote": false,
+                        "bitwardenSecretField": "token"
+                    }
+                ]
+            },
+            "status": { "checksum": "abc" }
+        });
+
+        let v1 =
+            convert_bitwarden_secret(v1beta1.clone(), "bitwarden-secret-operator.io/v1").unwrap();
+        assert_eq!(v1["apiVersion"], "bitwarden-secret-operator.io/v1");
+        assert_eq!(v1["metadata"]["resourceVersion"], "42");
+        assert_eq!(v1["status"]["checksum"], "abc");
+        assert_eq!(v1["spec"]["content"][0]["source"], json!({ "note": {} }));
+        assert_eq!(
+            v1["spec"]["content"][1]["source"],
+            json!({ "field": { "name": "password" } })
+        );
+
+        assert_eq!(
+            v1["spec"]["content"][2]["source"],
+            json!({ "value": "value" })
+        );
+
+        let back = convert_bitwarden_secret(v1, "bitwarden-secret-operator.io/v1beta1").unwrap();
+        assert_eq!(strip_nulls(back.clone()), v1beta1);
+        assert!(convert_bitwarden_secret(back, "bitwarden-secret-operator.io/v2").is_err());
+
+        // the fields v1beta1 can't hold are kept in an annotation
+        let v1 = json!({
+            "apiVersion": "bitwarden-secret-operator.io/v1",
+            "kind": "BitwardenSecret",
+            "metadata": { "name": "test", "namespace": "default" },
+            "spec": {
+                "content": [
+                    {
+                        "kubernetesSecretKey": "PASSWORD",
+                        "source": { "field": { "name": "password" } },
+                        "generate": { "length": 16 }
+                    },
+                    {
+                        "kubernetesSecretKey": "TOKEN",
+                        "source": { "field": { "name": "token" } },
+                        "rotation": { "interval": "720h", "previousKey": "PREVIOUS_TOKEN" }
+                    },
+                    { "kubernetesSecretKey": "NOTE", "source": { "note": {} } }
+                ]
+            }
+        });
+        let down =
+            convert_bitwarden_secret(v1.clone(), "bitwarden-secret-operator.io/v1beta1").unwrap();
+        assert!(
+            down["metadata"]["annotations"]["bitwarden-secret-operator.io/v1-fields"].is_string()
+        );
+        let up = convert_bitwarden_secret(down, "bitwarden-secret-operator.io/v1").unwrap();
+        assert_eq!(up["metadata"], v1["metadata"]);
+        assert_eq!(
+            serde_json::from_value::<Vec<schemas::ContentEntry>>(up["spec"]["content"].clone())
+                .unwrap(),
+            serde_json::from_value::<Vec<schemas::ContentEntry>>(v1["spec"]["content"].clone())
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use crate::config::Config;
+use futures::channel::mpsc;
+use futures::{StreamExt, TryStreamExt};
+use k8s_openapi::api::core::v1::Namespace;
+use k8s_openapi::NamespaceResourceScope;
+use kube::runtime::reflector::{self, ObjectRef, Store};
+use kube::runtime::{watcher, WatchStreamExt};
+use kube::{Api, Client, Resource, ResourceExt};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use tokio::task;
+use tracing::{info, warn};
+
+/// Namespaces and resources watched by the operator
+#[derive(Debug, Clone, Default)]
+pub struct ScopeSettings {
+    /// watched namespaces, every namespace when empty
+    pub namespaces: Vec<String>,
+    /// label selector of the watched namespaces
+    pub namespace_selector: Option<String>,
+    /// label selector of the watched BitwardenSecrets and BitwardenPushSecrets
+    pub label_selector: Option<String>,
+}
+
+impl ScopeSettings {
+    pub fn from_config(config: &Config) -> eyre::Result<Self> {
+        let settings = Self {
+            namespaces: config
+                .watch_namespaces
+                .iter()
+                .flatten()
+                .map(|x| x.trim())
+                .filter(|x| !x.is_empty())
+                .map(String::from)
+                .collect(),
+            namespace_selector: config
+                .watch_namespace_selector
+                .clone()
+                .filter(|x| !x.is_empty()),
+            label_selector: config
+                .watch_label_selector
+                .clone()
+                .filter(|x| !x.is_empty()),
+        };
+        if !settings.namespaces.is_empty() && settings.namespace_selector.is_some() {
+            eyre::bail!("WATCH_NAMESPACES and WATCH_NAMESPACE_SELECTOR can't be set together");
+        }
+        Ok(settings)
+    }
+
+    /// Returns true when every BitwardenSecret of the cluster is watched
+    pub(crate) fn is_cluster_wide(&self) -> bool {
+        self.namespaces.is_empty()
+            && self.namespace_selector.is_none()
+            && self.label_selector.is_none()
+    }
+
+    /// One Api per watched namespace, a single cluster-wide Api when the namespaces aren't listed
+    pub(crate) fn apis<K>(&self, client: &Client) -> Vec<Api<K>>
+    where
+        K: Resource<DynamicType = (), Scope = NamespaceResourceScope>,
+    {
+        if self.namespaces.is_empty() {
+            return vec![Api::all(client.clone())];
+        }
+        self.namespaces
+            .iter()
+            .map(|x| Api::namespaced(client.clone(), x))
+            .collect()
+    }
+
+    /// Position of the Api watching the namespace in `apis`
+    pub(crate) fn api_index(&self, namespace: &str) -> Option<usize> {
+        if self.namespaces.is_empty() {
+            return Some(0);
+        }
+        self.namespaces.iter().position(|x| x == namespace)
+    }
+
+    /// Watches the resources matching the label selector
+    pub(crate) fn watcher_config(&self) -> watcher::Config {
+        match &self.label_selector {
+            Some(selector) => watcher::Config::default().labels(selector),
+            None => watcher::Config::default(),
+        }
+    }
+}
+
+/// Checks whether a namespace is watched, namespaces matching the selector are followed by a
+/// reflector
+#[derive(Clone)]
+pub(crate) struct Scope {
+    settings: ScopeSettings,
+    namespaces: Option<Store<Namespace>>,
+    /// namespaces which stopped matching the selector since the operator started
+    left: Arc<Mutex<HashSet<String>>>,
+    subscribers: Arc<Mutex<Vec<mpsc::UnboundedSender<()>>>>,
+}
+
+impl Scope {
+    /// Starts following the namespaces matching the selector, waits for their first listing
+    pub(crate) async fn start(client: Client, settings: ScopeSettings) -> Self {
+        let subscribers = Arc::<Mutex<Vec<mpsc::UnboundedSender<()>>>>::default();
+        let left = Arc::<Mutex<HashSet<String>>>::default();
+        let Some(selector) = settings.namespace_selector.clone() else {
+            return Self {
+                settings,
+                namespaces: None,
+                left,
+                subscribers,
+            };
+        };
+
+        info!("Watching namespaces matching: {}", selector);
+        let (store, writer) = reflector::store();
+        let stream = watcher(
+            Api::<Namespace>::all(client),
+            watcher::Config::default().labels(&selector),
+        )
+        .default_backoff()
+        .reflect(writer)
+        .inspect_ok({
+            let left = left.clone();
+            move |event| match event {
+                watcher::Event::Applied(x) => {
+                    left.lock().unwrap().remove(&x.name_any());
+                }
+                watcher::Event::Deleted(x) => {
+                    left.lock().unwrap().insert(x.name_any());
+                }
+                watcher::Event::Restarted(_) => {}
+            }
+        })
+        .touched_objects();
+        let notified = subscribers.clone();
+        task::spawn(stream.for_each(move |res| {
+            match res {
+                // the store is updated before the namespace is emitted
+                Ok(_) => notified
+                    .lock()
+                    .unwrap()
+                    .retain(|x| x.unbounded_send(()).is_ok()),
+                Err(e) => warn!("watching namespaces failed: {}", e),
+            }
+            futures::future::ready(())
+        }));
+        if store.wait_until_ready().await.is_err() {
+            warn!("namespace watch stopped before listing the namespaces");
+        }
+        Self {
+            settings,
+            namespaces: Some(store),
+            left,
+            subscribers,
+        }
+    }
+
+    /// Emits every time a namespace matching the selector changes, so the controllers reconcile
+    /// the resources of the namespaces entering the scope
+    pub(crate) fn namespaces_changed(&self) -> Option<mpsc::UnboundedReceiver<()>> {
+        self.namespaces.as_ref()?;
+        let (sender, receiver) = mpsc::unbounded();
+        self.subscribers.lock().unwrap().push(sender);
+        Some(receiver)
+    }
+
+    pub(crate) fn settings(&self) -> &ScopeSettings {
+        &self.settings
+    }
+
+    /// Returns true when the namespace stopped matching the selector, its resources are released
+    /// rather than left to another operator
+    pub(crate) fn has_left(&self, namespace: &str) -> bool {
+        self.left.lock().unwrap().contains(namespace)
+    }
+
+    /// Returns true when the resources of the namespace are reconciled by this operator
+    pub(crate) fn allows(&self, namespace: &str) -> bool {
+        if !self.settings.namespaces.is_empty() {
+            return self.settings.namespaces.iter().any(|x| x == namespace);
+        }
+        match &self.namespaces {
+            Some(store) => store.get(&ObjectRef::new(namespace)).is_some(),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::operator::scope::{Scope, ScopeSettings};
+
+    #[test]
+    fn listed_namespaces_are_the_only_ones_allowed() {
+        let scope = |namespaces: &[&str]| Scope {
+            settings: ScopeSettings {
+                namespaces: namespaces.iter().map(|x| x.to_string()).collect(),
+                ..Default::default()
+            },
+            namespaces: None,
+            left: Default::default(),
+            subscribers: Default::default(),
+        };
+        assert!(scope(&[]).allows("team-a"));
+        assert!(scope(&["team-a", "team-b"]).allows("team-b"));
+        assert!(!scope(&["team-a"]).allows("team-b"));
+        assert_eq!(scope(&[]).settings.api_index("team-a"), Some(0));
+        assert_eq!(
+            scope(&["team-a", "team-b"]).settings.api_index("team-b"),
+            Some(1)
+        );
+        assert_eq!(scope(&["team-a"]).settings.api_index("team-b"), None);
+    }
+}
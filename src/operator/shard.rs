@@ -0,0 +1,336 @@
+use crate::config::Config;
+use crate::operator::controller::DEFAULT_FIELD_MANAGER;
+use crate::operator::leader::LeaseLock;
+use crate::operator::refresh::parse_interval;
+use chrono::{DateTime, Utc};
+use futures::channel::mpsc;
+use k8s_openapi::api::coordination::v1::Lease;
+use kube::api::{DeleteParams, ListParams, Preconditions};
+use kube::{Api, Client, Resource, ResourceExt};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+pub const SHARD_GROUP_LABEL: &str = "bitwarden-secret-operator.io/shard-group";
+
+/// Settings of the sharding between replicas, each replica holds a member Lease
+#[derive(Debug, Clone)]
+pub struct ShardingSettings {
+    /// prefix of the member Lease names, also the value of their shard group label
+    pub lease_prefix: String,
+    pub namespace: String,
+    /// identity of this replica, the pod name
+    pub identity: String,
+    /// how long a member Lease is valid without being renewed, the other replicas take over its
+    /// shard after it
+    pub lease_duration: Duration,
+    /// how often the member Lease is renewed and the members are listed
+    pub retry_period: Duration,
+}
+
+impl ShardingSettings {
+    /// Returns `None` when sharding is disabled
+    pub fn from_config(config: &Config) -> eyre::Result<Option<Self>> {
+        if config.sharding != Some(true) {
+            return Ok(None);
+        }
+
+        let settings = Self {
+            lease_prefix: config
+                .sharding_lease_prefix
+                .clone()
+                .unwrap_or_else(|| format!("{DEFAULT_FIELD_MANAGER}-shard")),
+            namespace: config
+                .pod_namespace
+                .clone()
+                .ok_or_else(|| eyre::eyre!("sharding requires POD_NAMESPACE"))?,
+            identity: config
+                .pod_name
+                .clone()
+                .ok_or_else(|| eyre::eyre!("sharding requires POD_NAME"))?,
+            lease_duration: match &config.sharding_lease_duration {
+                Some(x) => parse_interval(x)?,
+                None => Duration::from_secs(15),
+            },
+            retry_period: match &config.sharding_retry_period {
+                Some(x) => parse_interval(x)?,
+                None => Duration::from_secs(2),
+            },
+        };
+        if settings.retry_period.is_zero() || settings.retry_period >= settings.lease_duration {
+            eyre::bail!("SHARDING_RETRY_PERIOD must be shorter than SHARDING_LEASE_DURATION");
+        }
+        Ok(Some(settings))
+    }
+
+    fn lease_name(&self) -> String {
+        format!("{}-{}", self.lease_prefix, self.identity)
+    }
+}
+
+/// Returns true when the Lease has expired, i.e. its holder stopped renewing it
+pub fn is_expired(lease: &Lease, now: DateTime<Utc>) -> bool {
+    let Some(spec) = &lease.spec else {
+        return true;
+    };
+    match (&spec.renew_time, spec.lease_duration_seconds) {
+        (Some(renew_time), Some(duration)) => chrono::Duration::try_seconds(duration.into())
+            .is_none_or(|duration| now >= renew_time.0 + duration),
+        _ => true,
+    }
+}
+
+/// Sorted identities of the holders of the member Leases which haven't expired
+pub fn live_members(leases: &[Lease], now: DateTime<Utc>) -> Vec<String> {
+    let mut members = leases
+        .iter()
+        .filter(|x| !is_expired(x, now))
+        .filter_map(|x| x.spec.as_ref()?.holder_identity.clone())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>();
+    members.sort();
+    members.dedup();
+    members
+}
+
+/// Member owning the key, with rendezvous hashing: when a member joins or leaves only the keys it
+/// gains or loses change owner
+pub fn shard_owner<'a>(members: &'a [String], key: &str) -> Option<&'a str> {
+    members
+        .iter()
+        .max_by_key(|member| {
+            let digest = Sha256::new()
+                .chain_update(member.as_bytes())
+                .chain_update([0])
+                .chain_update(key.as_bytes())
+                .finalize();
+            let weight = u64::from_be_bytes(digest[..8].try_into().unwrap());
+            (weight, *member)
+        })
+        .map(String::as_str)
+}
+
+/// Shard of this replica, shared with the controllers
+#[derive(Clone)]
+pub(crate) struct Shard {
+    identity: String,
+    members: Arc<RwLock<Vec<String>>>,
+    subscribers: Arc<Mutex<Vec<mpsc::UnboundedSender<()>>>>,
+}
+
+impl Shard {
+    fn new(identity: String) -> Self {
+        Self {
+            identity,
+            members: Default::default(),
+            subscribers: Default::default(),
+        }
+    }
+
+    /// Returns true when the namespace/name of the resource falls in the shard of this replica
+    pub(crate) fn owns<K: Resource>(&self, obj: &K) -> bool {
+        let key = format!("{}/{}", obj.namespace().unwrap_or_default(), obj.name_any());
+        let members = self.members.read().unwrap();
+        shard_owner(&members, &key) == Some(self.identity.as_str())
+    }
+
+    /// Emits every time the members change, so the controllers reconcile the objects they gained
+    pub(crate) fn rebalanced(&self) -> mpsc::UnboundedReceiver<()> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    fn set_members(&self, members: Vec<String>) {
+        let mut current = self.members.write().unwrap();
+        if *current == members {
+            return;
+        }
+        info!("shard members: {}", members.join(", "));
+        metrics::gauge!("shard_members").set(members.len() as f64);
+        *current = members;
+        drop(current);
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|x| x.unbounded_send(()).is_ok());
+    }
+}
+
+/// Keeps the member Lease of this replica renewed and the members of the shard group up to date
+pub(crate) struct ShardMembership {
+    lock: LeaseLock,
+    leases: Api<Lease>,
+    settings: ShardingSettings,
+    shard: Shard,
+}
+
+impl ShardMembership {
+    pub(crate) fn new(client: Client, settings: ShardingSettings) -> Self {
+        let lock = LeaseLock::new(
+            client.clone(),
+            &settings.namespace,
+            settings.lease_name(),
+            settings.identity.clone(),
+            settings.lease_duration,
+        )
+        .with_labels(BTreeMap::from([(
+            SHARD_GROUP_LABEL.to_string(),
+            settings.lease_prefix.clone(),
+        )]));
+        Self {
+            lock,
+            leases: Api::namespaced(client, &settings.namespace),
+            shard: Shard::new(settings.identity.clone()),
+            settings,
+        }
+    }
+
+    pub(crate) fn shard(&self) -> Shard {
+        self.shard.clone()
+    }
+
+    /// Member Lease of this replica, released on shutdown
+    pub(crate) fn lease(&self) -> LeaseLock {
+        self.lock.clone()
+    }
+
+    /// Waits until this replica is a member of the shard group
+    pub(crate) async fn join(&self) {
+        info!(
+            "{} joining the shard group: {}/{}",
+            self.settings.identity, self.settings.namespace, self.settings.lease_prefix
+        );
+        loop {
+            let joined = match self.lock.try_acquire_or_renew().await {
+                Ok(true) => self.refresh(true).await,
+                Ok(false) => Ok(()),
+                Err(e) => Err(e),
+            };
+            match joined {
+                Ok(()) if self.is_member() => break,
+                Ok(()) => {}
+                Err(e) => warn!("joining the shard group failed: {}", e),
+            }
+            tokio::time::sleep(self.settings.retry_period).await;
+        }
+        info!("{} joined the shard group", self.settings.identity);
+    }
+
+    /// Renews the member Lease and follows the members until the process stops
+    pub(crate) async fn run(self) {
+        let mut renewed = Instant::now();
+        loop {
+            tokio::time::sleep(self.settings.retry_period).await;
+            match self.lock.try_acquire_or_renew().await {
+                Ok(true) => renewed = Instant::now(),
+                Ok(false) => warn!(
+                    "{} member Lease is held by another replica",
+                    self.settings.identity
+                ),
+                Err(e) => warn!("member Lease renewal failed: {}", e),
+            }
+            // the other members take over the shard once the Lease expires, stop reconciling
+            // before that happens
+            let valid =
+                renewed.elapsed() + self.settings.retry_period < self.settings.lease_duration;
+            if let Err(e) = self.refresh(valid).await {
+                warn!("listing the shard members failed: {}", e);
+            }
+        }
+    }
+
+    fn is_member(&self) -> bool {
+        let members = self.shard.members.read().unwrap();
+        members.contains(&self.settings.identity)
+    }
+
+    /// Lists the member Leases, updates the shard and deletes the expired Leases left behind by
+    /// replicas which are gone. This replica is left out when its Lease isn't `valid`.
+    async fn refresh(&self, valid: bool) -> kube::Result<()> {
+        let params = ListParams::default().labels(&format!(
+            "{}={}",
+            SHARD_GROUP_LABEL, self.settings.lease_prefix
+        ));
+        let leases = self.leases.list(&params).await?.items;
+        let now = Utc::now();
+        let mut members = live_members(&leases, now);
+        if !valid {
+            members.retain(|x| x != &self.settings.identity);
+        }
+        self.shard.set_members(members);
+
+        for lease in leases.iter().filter(|x| is_expired(x, now)) {
+            // the resourceVersion precondition keeps a Lease renewed in the meantime
+            let params = DeleteParams {
+                preconditions: Some(Preconditions {
+                    uid: None,
+                    resource_version: lease.resource_version(),
+                }),
+                ..DeleteParams::default()
+            };
+            if self.leases.delete(&lease.name_any(), &params).await.is_ok() {
+                info!("expired member Lease: {} deleted", lease.name_any());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::operator::shard::{live_members, shard_owner};
+    use chrono::{TimeZone, Utc};
+    use k8s_openapi::api::coordination::v1::{Lease, LeaseSpec};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::MicroTime;
+
+    #[test]
+    fn expired_members_are_left_out() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let lease = |holder: &str, seconds_ago: i64| Lease {
+            spec: Some(LeaseSpec {
+                holder_identity: Some(holder.to_string()),
+                lease_duration_seconds: Some(15),
+                renew_time: Some(MicroTime(
+                    now - chrono::Duration::try_seconds(seconds_ago).unwrap(),
+                )),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let leases = [
+            lease("operator-1", 5),
+            lease("operator-0", 0),
+            lease("operator-2", 15),
+            Lease::default(),
+        ];
+        assert_eq!(live_members(&leases, now), ["operator-0", "operator-1"]);
+    }
+
+    #[test]
+    fn only_the_keys_of_a_leaving_member_move() {
+        let members = ["a", "b", "c"].map(String::from);
+        let keys = (0..300)
+            .map(|x| format!("default/secret-{x}"))
+            .collect::<Vec<_>>();
+        let owners = keys
+            .iter()
+            .map(|x| shard_owner(&members, x).unwrap())
+            .collect::<Vec<_>>();
+        for member in &members {
+            // every member gets a share of the keys
+            assert!(owners.iter().filter(|x| *x == member).count() > 50);
+        }
+
+        let remaining = ["a", "c"].map(String::from);
+        for (key, owner) in keys.iter().zip(owners) {
+            let new_owner = shard_owner(&remaining, key).unwrap();
+            if owner != "b" {
+                assert_eq!(new_owner, owner);
+            }
+        }
+        assert_eq!(shard_owner(&[], "default/secret"), None);
+    }
+}
@@ -0,0 +1,333 @@
+use crate::bitwarden_cli::BitwardenCliClient;
+use crate::config::Config;
+use crate::operator::certificates::{CertificateManager, CertificateSettings};
+use crate::operator::controller::DEFAULT_FIELD_MANAGER;
+use crate::operator::schemas::{bitwarden_secret_crd, convert_bitwarden_secret, BitwardenSecret};
+use crate::operator::{validate_bitwarden_secret, verify_bitwarden_items};
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use axum_server::tls_rustls::RustlsConfig;
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use k8s_openapi::ByteString;
+use kube::api::{Patch, PatchParams};
+use kube::core::admission::{AdmissionRequest, AdmissionResponse, AdmissionReview, Operation};
+use kube::core::conversion::{ConversionRequest, ConversionResponse, ConversionReview};
+use kube::core::{DynamicObject, Status};
+use kube::{Api, Client, CustomResourceExt, ResourceExt};
+use serde_json::json;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task;
+use tracing::{info, warn};
+
+/// How often the self-managed certificate is checked for rotation
+const CERTIFICATE_CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+pub(crate) const VALIDATE_PATH: &str = "/validate-bitwardensecret";
+pub(crate) const CONVERT_PATH: &str = "/convert";
+
+/// Where the serving certificate of the webhook server comes from
+#[derive(Debug, Clone)]
+pub enum WebhookTls {
+    /// PEM files mounted in the operator, e.g. issued by cert-manager
+    Files {
+        cert: PathBuf,
+        key: PathBuf,
+        ca: Option<PathBuf>,
+    },
+    /// generated, stored and rotated by the operator itself
+    SelfManaged(CertificateSettings),
+}
+
+/// Settings of the admission webhook server
+#[derive(Debug, Clone)]
+pub struct WebhookSettings {
+    pub endpoint: SocketAddr,
+    pub tls: WebhookTls,
+    /// also checks that the referenced Bitwarden items and fields exist
+    pub verify_vault: bool,
+    /// Service in front of the webhook server, configured as the conversion webhook of the CRD
+    pub service: Option<WebhookService>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookService {
+    pub namespace: String,
+    pub name: String,
+}
+
+impl WebhookSettings {
+    /// Returns `None` when the webhook is not configured
+    pub fn from_config(config: &Config) -> eyre::Result<Option<Self>> {
+        let tls = match (&config.webhook_tls_cert, &config.webhook_tls_key) {
+            (Some(cert), Some(key)) => WebhookTls::Files {
+                cert: cert.clone(),
+                key: key.clone(),
+                ca: config.webhook_tls_ca.clone(),
+            },
+            _ if config.webhook_self_managed_tls == Some(true) => {
+                WebhookTls::SelfManaged(CertificateSettings::from_config(config)?)
+            }
+            _ => return Ok(None),
+        };
+
+        let endpoint = config
+            .webhook_endpoint
+            .as_deref()
+            .unwrap_or("0.0.0.0:8443")
+            .parse()?;
+        let verify_vault = config.webhook_verify_vault.unwrap_or(false);
+        let service = config
+            .pod_namespace
+            .clone()
+            .map(|namespace| WebhookService {
+                namespace,
+                name: config
+                    .webhook_service_name
+                    .clone()
+                    .unwrap_or_else(|| format!("{DEFAULT_FIELD_MANAGER}-webhook")),
+            });
+
+        Ok(Some(Self {
+            endpoint,
+            tls,
+            verify_vault,
+            service,
+        }))
+    }
+}
+
+#[derive(Clone)]
+struct WebhookContext {
+    cli: Arc<BitwardenCliClient>,
+    verify_vault: bool,
+}
+
+/// Loads the serving certificate, along with the CA bundle to trust it when it is known
+async fn load_tls_config(
+    tls: WebhookTls,
+    client: Client,
+) -> eyre::Result<(RustlsConfig, Option<String>)> {
+    match tls {
+        WebhookTls::Files { cert, key, ca } => {
+            let ca_bundle = match ca {
+                Some(ca) => Some(tokio::fs::read_to_string(ca).await?),
+                None => None,
+            };
+            Ok((RustlsConfig::from_pem_file(cert, key).await?, ca_bundle))
+        }
+        WebhookTls::SelfManaged(settings) => {
+            let manager = CertificateManager::new(client, settings);
+            let certificate = manager.ensure().await?;
+            let ca_bundle = certificate.ca_bundle.clone();
+            let config =
+                RustlsConfig::from_pem(certificate.cert_pem.into(), certificate.key_pem.into())
+                    .await?;
+
+            // background task to rotate the certificate before it expires
+            let reloaded = config.clone();
+            task::spawn(async move {
+                loop {
+                    tokio::time::sleep(CERTIFICATE_CHECK_INTERVAL).await;
+                    let result = match manager.ensure().await {
+                        Ok(x) => reloaded
+                            .reload_from_pem(x.cert_pem.into(), x.key_pem.into())
+                            .await
+                            .map_err(eyre::Report::from),
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = result {
+                        warn!("webhook certificate rotation failed: {}", e);
+                    }
+                }
+            });
+            Ok((config, Some(ca_bundle)))
+        }
+    }
+}
+
+/// Points the conversion webhook of the CRD to the webhook server, then serves every version of
+/// the BitwardenSecret, converted from and to the stored one
+async fn configure_conversion_webhook(
+    client: Client,
+    service: &WebhookService,
+    ca_bundle: Option<String>,
+) -> eyre::Result<()> {
+    let mut client_config = json!({
+        "service": {
+            "namespace": service.namespace,
+            "name": service.name,
+            "path": CONVERT_PATH,
+            "port": 443,
+        }
+    });
+    if let Some(ca_bundle) = ca_bundle {
+        client_config["caBundle"] = json!(ByteString(ca_bundle.into_bytes()));
+    }
+    // the versions are a list, replaced as a whole by the merge patch
+    let mut versions = bitwarden_secret_crd().spec.versions;
+    for version in &mut versions {
+        version.served = true;
+    }
+    let patch = json!({
+        "spec": {
+            "versions": versions,
+            "conversion": {
+                "strategy": "Webhook",
+                "webhook": {
+                    "conversionReviewVersions": ["v1"],
+                    "clientConfig": client_config,
+                }
+            }
+        }
+    });
+
+    let crd_name = BitwardenSecret::crd_name();
+    Api::<CustomResourceDefinition>::all(client)
+        .patch(crd_name, &PatchParams::default(), &Patch::Merge(&patch))
+        .await?;
+    info!(
+        "CustomResourceDefinition: {} conversion webhook configured",
+        crd_name
+    );
+    Ok(())
+}
+
+pub async fn start_webhook_server(
+    settings: WebhookSettings,
+    cli: Arc<BitwardenCliClient>,
+    client: Client,
+) -> eyre::Result<()> {
+    let (config, ca_bundle) = load_tls_config(settings.tls, client.clone()).await?;
+    if let Some(service) = &settings.service {
+        configure_conversion_webhook(client, service, ca_bundle).await?;
+    }
+    let context = Arc::new(WebhookContext {
+        cli,
+        verify_vault: settings.verify_vault,
+    });
+    let app = Router::new()
+        .route(VALIDATE_PATH, post(validate))
+        .route(CONVERT_PATH, post(convert))
+        .with_state(context);
+
+    info!(
+        "HTTPS admission webhook server listening on: {}",
+        settings.endpoint
+    );
+    axum_server::bind_rustls(settings.endpoint, config)
+        .serve(app.into_make_service())
+        .await?;
+    Ok(())
+}
+
+async fn validate(
+    State(ctx): State<Arc<WebhookContext>>,
+    Json(review): Json<AdmissionReview<BitwardenSecret>>,
+) -> Json<AdmissionReview<DynamicObject>> {
+    let request: AdmissionRequest<BitwardenSecret> = match review.try_into() {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("invalid admission review: {}", e);
+            return Json(AdmissionResponse::invalid(e.to_string()).into_review());
+        }
+    };
+
+    let response = AdmissionResponse::from(&request);
+    let Some(obj) = request
+        .object
+        .filter(|_| request.operation != Operation::Delete)
+        .filter(|x| !skips_validation(x, request.old_object.as_ref()))
+    else {
+        return Json(response.into_review());
+    };
+
+    metrics::counter!("webhook_validations_total").increment(1);
+    let result = if ctx.verify_vault {
+        verify_bitwarden_items(ctx.cli.clone(), &obj).await
+    } else {
+        validate_bitwarden_secret(&obj)
+    };
+
+    match result {
+        Ok(()) => Json(response.into_review()),
+        Err(e) => {
+            info!("BitwardenSecret: {} denied: {}", obj.name_any(), e);
+            metrics::counter!("webhook_validations_denied_total").increment(1);
+            Json(response.deny(e.to_string()).into_review())
+        }
+    }
+}
+
+/// Objects being deleted, or updated without changing their spec (e.g. to remove the finalizer),
+/// are admitted so they can still be deleted once invalid under newer rules
+fn skips_validation(obj: &BitwardenSecret, old: Option<&BitwardenSecret>) -> bool {
+    obj.metadata.deletion_timestamp.is_some()
+        || old.is_some_and(|old| {
+            serde_json::to_value(&old.spec).ok() == serde_json::to_value(&obj.spec).ok()
+        })
+}
+
+async fn convert(Json(review): Json<ConversionReview>) -> Json<ConversionReview> {
+    let request = match ConversionRequest::from_review(review) {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("invalid conversion review: {}", e);
+            let status = Status::failure(&e.to_string(), "InvalidRequest");
+            return Json(ConversionResponse::invalid(status).into_review());
+        }
+    };
+
+    metrics::counter!("webhook_conversions_total").increment(1);
+    let desired_api_version = request.desired_api_version.clone();
+    let converted = request
+        .objects
+        .iter()
+        .map(|x| convert_bitwarden_secret(x.clone(), &desired_api_version))
+        .collect::<eyre::Result<Vec<_>>>();
+
+    let response = ConversionResponse::for_request(request);
+    match converted {
+        Ok(objects) => Json(response.success(objects).into_review()),
+        Err(e) => {
+            warn!("conversion to {} failed: {}", desired_api_version, e);
+            let status = Status::failure(&e.to_string(), "ConversionFailed");
+            Json(response.failure(status).into_review())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::operator::schemas::{BitwardenSecret, BitwardenSecretSpec};
+    use crate::operator::webhook::skips_validation;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+
+    #[test]
+    fn finalizer_removal_skips_validation() {
+        let old = BitwardenSecret::new(
+            "test",
+            BitwardenSecretSpec {
+                refresh_interval: Some("not an interval".to_string()),
+                ..Default::default()
+            },
+        );
+        assert!(!skips_validation(&old, None));
+
+        let mut released = old.clone();
+        released.metadata.finalizers = None;
+        assert!(skips_validation(&released, Some(&old)));
+
+        let mut deleted = old.clone();
+        deleted.metadata.deletion_timestamp = Some(Time(chrono::Utc::now()));
+        deleted.spec.refresh_interval = Some("1h".to_string());
+        assert!(skips_validation(&deleted, Some(&old)));
+
+        let mut changed = old.clone();
+        changed.spec.refresh_interval = Some("1h".to_string());
+        assert!(!skips_validation(&changed, Some(&old)));
+    }
+}
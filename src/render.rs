@@ -0,0 +1,208 @@
+use crate::bitwarden_cli::{BitwardenCliClient, BitwardenItem};
+use crate::config::Config;
+use crate::operator::generate_secret_from_bitwarden_secret;
+use crate::operator::schemas::{
+    convert_bitwarden_secret, BitwardenSecret, BitwardenSecretError, GROUP, STORAGE_VERSION,
+};
+use chrono::Utc;
+use clap::Args;
+use k8s_openapi::api::core::v1::Secret;
+use serde::Deserialize;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const REDACTED: &str = "<redacted>";
+
+/// Prints the Secrets rendered from BitwardenSecret manifests, without deploying them
+#[derive(Debug, Clone, Args)]
+pub struct RenderArgs {
+    /// YAML file holding the BitwardenSecrets, read from stdin when omitted or `-`
+    pub file: Option<PathBuf>,
+    /// JSON array of Bitwarden items, e.g. the output of `bw list items`, read instead of the vault
+    #[arg(long)]
+    pub fixture: Option<PathBuf>,
+    /// Namespace of the BitwardenSecrets which don't set one
+    #[arg(long, default_value = "default")]
+    pub namespace: String,
+    /// Prints the values of the Secrets instead of redacting them
+    #[arg(long)]
+    pub reveal: bool,
+}
+
+/// Returns true when the object is a BitwardenSecret, of any served version
+pub fn is_bitwarden_secret(object: &serde_json::Value) -> bool {
+    object.get("kind").and_then(|x| x.as_str()) == Some("BitwardenSecret")
+        && object
+            .get("apiVersion")
+            .and_then(|x| x.as_str())
+            .is_some_and(|x| x.starts_with(&format!("{GROUP}/")))
+}
+
+/// Parses the BitwardenSecret, converted to the storage version
+pub fn parse_bitwarden_secret(object: serde_json::Value) -> eyre::Result<BitwardenSecret> {
+    let converted = convert_bitwarden_secret(object, &format!("{GROUP}/{STORAGE_VERSION}"))?;
+    Ok(serde_json::from_value(converted)?)
+}
+
+/// Client resolving the items from the fixture, or from the configured vault without writing to
+/// it: items which would be generated or rotated are only kept in memory
+pub async fn render_client(
+    config: &Config,
+    fixture: Option<&Path>,
+) -> eyre::Result<Arc<BitwardenCliClient>> {
+    let Some(fixture) = fixture else {
+        let cli = BitwardenCliClient::from_config(config)?.dry_run();
+        cli.login().await?;
+        cli.unlock().await?;
+        cli.sync().await?;
+        return Ok(Arc::new(cli));
+    };
+    let items = std::fs::read_to_string(fixture)
+        .map_err(|e| eyre::eyre!("{} couldn't be read: {e}", fixture.display()))?;
+    let items: Vec<BitwardenItem> = serde_json::from_str(&items)
+        .map_err(|e| eyre::eyre!("{} is invalid: {e}", fixture.display()))?;
+    Ok(Arc::new(BitwardenCliClient::from_fixture(items)))
+}
+
+/// Renders the Secret the operator would write for the BitwardenSecret
+pub async fn render_secret(
+    cli: Arc<BitwardenCliClient>,
+    mut bitwarden_secret: BitwardenSecret,
+    namespace: &str,
+) -> Result<Secret, BitwardenSecretError> {
+    bitwarden_secret
+        .metadata
+        .namespace
+        .get_or_insert_with(|| namespace.to_string());
+    let rendered =
+        generate_secret_from_bitwarden_secret(cli, Arc::new(bitwarden_secret), Utc::now()).await?;
+    Ok(rendered.secret)
+}
+
+/// Replaces the values of the Secret, its keys are kept
+pub fn redact(mut secret: Secret) -> Secret {
+    if let Some(data) = secret.data.take() {
+        secret.string_data = Some(
+            data.into_keys()
+                .map(|x| (x, REDACTED.to_string()))
+                .collect(),
+        );
+    }
+    secret
+}
+
+pub async fn run(config: &Config, args: &RenderArgs) -> eyre::Result<()> {
+    let mut input = String::new();
+    match &args.file {
+        Some(path) if path.as_os_str() != "-" => {
+            input = std::fs::read_to_string(path)
+                .map_err(|e| eyre::eyre!("{} couldn't be read: {e}", path.display()))?;
+        }
+        _ => {
+            std::io::stdin().read_to_string(&mut input)?;
+        }
+    }
+
+    let mut bitwarden_secrets = vec![];
+    for document in serde_yaml::Deserializer::from_str(&input) {
+        let object = serde_json::Value::deserialize(document)?;
+        if object.is_null() {
+            continue;
+        }
+        if !is_bitwarden_secret(&object) {
+            eyre::bail!("only BitwardenSecrets can be rendered");
+        }
+        bitwarden_secrets.push(parse_bitwarden_secret(object)?);
+    }
+
+    let cli = render_client(config, args.fixture.as_deref()).await?;
+    let mut documents = vec![];
+    for bitwarden_secret in bitwarden_secrets {
+        let secret = render_secret(cli.clone(), bitwarden_secret, &args.namespace).await?;
+        let secret = if args.reveal { secret } else { redact(secret) };
+        documents.push(serde_yaml::to_string(&secret)?);
+    }
+    print!("{}", documents.join("---\n"));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bitwarden_cli::BitwardenCliClient;
+    use crate::render::{parse_bitwarden_secret, redact, render_secret};
+    use k8s_openapi::ByteString;
+    use std::sync::Arc;
+
+    const BITWARDEN_SECRET: &str = r#"
+apiVersion: bitwarden-secret-operator.io/v1
+kind: BitwardenSecret
+metadata:
+  name: my-secret
+spec:
+  bitwardenId: 00000000-0000-0000-0000-000000000000
+  content:
+  - kubernetesSecretKey: FIELD
+    source:
+      field:
+        name: super-secret-field
+  - kubernetesSecretKey: VALUE
+    source:
+      value: literal
+"#;
+
+    #[tokio::test]
+    async fn renders_generated_content_from_a_fixture() {
+        let cli = Arc::new(BitwardenCliClient::from_fixture(vec![]));
+        let object = serde_yaml::from_str(
+            r#"
+apiVersion: bitwarden-secret-operator.io/v1
+kind: BitwardenSecret
+metadata:
+  name: my-secret
+spec:
+  content:
+  - kubernetesSecretKey: PASSWORD
+    source:
+      field:
+        name: password
+    generate:
+      length: 24
+      charset: [Numbers, Special]
+"#,
+        )
+        .unwrap();
+        let bitwarden_secret = parse_bitwarden_secret(object).unwrap();
+
+        let secret = render_secret(cli, bitwarden_secret, "team-a")
+            .await
+            .unwrap();
+        let password = String::from_utf8(secret.data.unwrap()["PASSWORD"].0.clone()).unwrap();
+        assert_eq!(password.len(), 24);
+        assert!(password.chars().all(|x| "23456789!@#$%^&*".contains(x)));
+        assert!(password.chars().any(|x| x.is_ascii_digit()));
+        assert!(password.chars().any(|x| !x.is_ascii_digit()));
+    }
+
+    #[tokio::test]
+    async fn renders_from_a_fixture() {
+        let item =
+            serde_json::from_str(&std::fs::read_to_string("tests/bitwarden-fields.json").unwrap())
+                .unwrap();
+        let cli = Arc::new(BitwardenCliClient::from_fixture(vec![item]));
+        let object = serde_yaml::from_str(BITWARDEN_SECRET).unwrap();
+        let bitwarden_secret = parse_bitwarden_secret(object).unwrap();
+
+        let secret = render_secret(cli, bitwarden_secret, "team-a")
+            .await
+            .unwrap();
+        assert_eq!(secret.metadata.namespace.as_deref(), Some("team-a"));
+        assert!(secret.metadata.owner_references.is_none());
+        let data = secret.data.clone().unwrap();
+        assert_eq!(data["FIELD"], ByteString(b"super-secret".to_vec()));
+
+        let redacted = redact(secret);
+        assert!(redacted.data.is_none());
+        assert_eq!(redacted.string_data.unwrap()["VALUE"], "<redacted>");
+    }
+}
@@ -0,0 +1,96 @@
+use crate::bitwarden_cli::BitwardenCliClient;
+use crate::config::Config;
+use crate::operator::refresh::parse_interval;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+/// What happens when the operator is asked to stop
+#[derive(Debug, Clone)]
+pub struct ShutdownSettings {
+    /// how long in-flight reconciles are given to finish
+    pub timeout: Duration,
+    /// logs out of the vault, it is always locked
+    pub logout: bool,
+    /// directory of the CLI wiped once logged out
+    pub wipe_appdata: Option<PathBuf>,
+}
+
+impl ShutdownSettings {
+    pub fn from_config(config: &Config) -> eyre::Result<Self> {
+        let wipe_appdata = match config.bw_wipe_appdata_on_exit {
+            Some(true) => Some(config.bitwardencli_appdata_dir.clone().ok_or_else(|| {
+                eyre::eyre!("BW_WIPE_APPDATA_ON_EXIT requires BITWARDENCLI_APPDATA_DIR")
+            })?),
+            _ => None,
+        };
+        Ok(Self {
+            timeout: match &config.shutdown_timeout {
+                Some(x) => parse_interval(x)?,
+                None => Duration::from_secs(30),
+            },
+            logout: config.bw_logout_on_exit == Some(true),
+            wipe_appdata,
+        })
+    }
+}
+
+/// Resolves once SIGTERM or SIGINT is received, shared by everything which has to stop
+#[derive(Clone)]
+pub struct Shutdown {
+    receiver: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    pub fn on_signal() -> eyre::Result<Self> {
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let (sender, receiver) = watch::channel(false);
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = terminate.recv() => info!("SIGTERM received, shutting down..."),
+                _ = interrupt.recv() => info!("SIGINT received, shutting down..."),
+            }
+            let _ = sender.send(true);
+        });
+        Ok(Self { receiver })
+    }
+
+    /// Waits until the shutdown is requested
+    pub async fn requested(mut self) {
+        let _ = self.receiver.wait_for(|x| *x).await;
+    }
+}
+
+/// Locks the vault, then logs out and wipes the CLI directory when configured, so no unlocked
+/// session is left on disk
+pub async fn close_vault(cli: &BitwardenCliClient, settings: &ShutdownSettings) {
+    if let Err(e) = cli.lock().await {
+        warn!("{}", e);
+    }
+    if settings.logout || settings.wipe_appdata.is_some() {
+        if let Err(e) = cli.logout().await {
+            warn!("{}", e);
+        }
+    }
+    if let Some(appdata) = &settings.wipe_appdata {
+        info!("wiping {}", appdata.display());
+        let entries = match std::fs::read_dir(appdata) {
+            Ok(x) => x,
+            Err(e) => return warn!("{} couldn't be read: {}", appdata.display(), e),
+        };
+        // the directory itself may be a mounted volume, only its content is removed
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let result = match path.is_dir() {
+                true => std::fs::remove_dir_all(&path),
+                false => std::fs::remove_file(&path),
+            };
+            if let Err(e) = result {
+                warn!("{} couldn't be removed: {}", path.display(), e);
+            }
+        }
+    }
+}